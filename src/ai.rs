@@ -0,0 +1,696 @@
+use std::collections::VecDeque;
+
+use crate::controller::SnakeController;
+use crate::level::WrapMode;
+use crate::sim::{Direction, GridPos, Simulation};
+
+/// The board as the AIs see it for the coming tick: walls and every living
+/// snake are blocked, except for the tail tips of other snakes, which move
+/// out of the way. A snake's own tail tip is still there when its head
+/// arrives, so it stays blocked.
+struct Grid {
+    size: (i32, i32),
+    wrap: WrapMode,
+    blocked: Vec<bool>,
+    /// Cells another snake's head could move into, where a head-on crash
+    /// would kill both.
+    risky: Vec<bool>,
+}
+
+impl Grid {
+    fn new(sim: &Simulation, player: usize) -> Self {
+        let level = sim.level();
+        let (w, h) = level.size;
+        let mut grid = Grid {
+            size: level.size,
+            wrap: level.wrap,
+            blocked: vec![false; (w * h) as usize],
+            risky: vec![false; (w * h) as usize],
+        };
+        for &pos in &level.obstacles {
+            grid.set(pos, true);
+        }
+        for (i, other) in sim.players().iter().enumerate().filter(|(_, p)| p.is_alive()) {
+            let snake = other.snake();
+            let cells: Vec<GridPos> = snake.cells().collect();
+            let keep = if i == player { cells.len() } else { cells.len() - 1 };
+            for &pos in &cells[..keep] {
+                grid.set(pos, true);
+            }
+            if i != player {
+                let near: Vec<usize> = grid.neighbours(snake.head.pos).map(|(_, pos)| grid.index(pos)).collect();
+                for index in near {
+                    grid.risky[index] = true;
+                }
+            }
+        }
+        grid
+    }
+
+    fn index(&self, pos: GridPos) -> usize {
+        (pos.y * self.size.0 + pos.x) as usize
+    }
+
+    fn set(&mut self, pos: GridPos, blocked: bool) {
+        let index = self.index(pos);
+        self.blocked[index] = blocked;
+    }
+
+    fn is_free(&self, pos: GridPos) -> bool {
+        !self.blocked[self.index(pos)]
+    }
+
+    fn is_risky(&self, pos: GridPos) -> bool {
+        self.risky[self.index(pos)]
+    }
+
+    /// Cells one step away from `pos` that are on the board, blocked or not.
+    fn neighbours(&self, pos: GridPos) -> impl Iterator<Item = (Direction, GridPos)> + '_ {
+        Direction::ALL
+            .into_iter()
+            .filter_map(move |dir| Some((dir, GridPos::new_from_move(pos, dir, self.size, self.wrap)?)))
+    }
+
+    /// Steps from `from` to `to` through free cells, `to` included, or `None`
+    /// if it cannot be reached. `to` itself may be blocked.
+    fn path(&self, from: GridPos, to: GridPos) -> Option<Vec<GridPos>> {
+        self.search(from, |pos| self.is_free(pos) || pos == to, |pos| pos == to)
+    }
+
+    /// Steps from `from` to the nearest cell that is a `goal`, moving only
+    /// through cells that are `open`, or `None` if there is no such cell.
+    fn search(
+        &self,
+        from: GridPos,
+        open: impl Fn(GridPos) -> bool,
+        goal: impl Fn(GridPos) -> bool,
+    ) -> Option<Vec<GridPos>> {
+        let mut came_from: Vec<Option<GridPos>> = vec![None; self.blocked.len()];
+        let mut queue = VecDeque::from([from]);
+        came_from[self.index(from)] = Some(from);
+        while let Some(pos) = queue.pop_front() {
+            if goal(pos) {
+                let mut path = vec![pos];
+                let mut at = pos;
+                while let Some(prev) = came_from[self.index(at)].filter(|&prev| prev != from) {
+                    path.push(prev);
+                    at = prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (_, next) in self.neighbours(pos) {
+                let index = self.index(next);
+                if came_from[index].is_none() && open(next) {
+                    came_from[index] = Some(pos);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// How many free cells can be reached from `from`.
+    fn area(&self, from: GridPos) -> usize {
+        let mut seen = vec![false; self.blocked.len()];
+        let mut stack = vec![from];
+        seen[self.index(from)] = true;
+        let mut count = 0;
+        while let Some(pos) = stack.pop() {
+            count += 1;
+            for (_, next) in self.neighbours(pos) {
+                let index = self.index(next);
+                if !seen[index] && self.is_free(next) {
+                    seen[index] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        count
+    }
+
+    /// Steps from `a` to `b`, counting the shorter way round wrapping edges.
+    fn distance(&self, a: GridPos, b: GridPos) -> i32 {
+        let (w, h) = self.size;
+        let (dx, dy) = ((a.x - b.x).abs(), (a.y - b.y).abs());
+        let dx = if matches!(self.wrap, WrapMode::Both | WrapMode::Horizontal) { dx.min(w - dx) } else { dx };
+        let dy = if matches!(self.wrap, WrapMode::Both | WrapMode::Vertical) { dy.min(h - dy) } else { dy };
+        dx + dy
+    }
+
+    fn direction_to(&self, from: GridPos, to: GridPos) -> Option<Direction> {
+        self.neighbours(from).find(|&(_, pos)| pos == to).map(|(dir, _)| dir)
+    }
+
+    /// The free cells next to the head, with the turn that leads there.
+    fn moves(&self, sim: &Simulation, player: usize) -> Vec<(Direction, GridPos)> {
+        let head = sim.players()[player].snake().head.pos;
+        self.neighbours(head).filter(|&(_, pos)| self.is_free(pos)).collect()
+    }
+}
+
+/// Heads straight for the food, never into something that kills it at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GreedyController;
+
+impl SnakeController for GreedyController {
+    fn decide(&mut self, sim: &Simulation, player: usize) -> Option<Direction> {
+        let grid = Grid::new(sim, player);
+        let food = sim.food().map(|food| food.pos);
+        grid.moves(sim, player)
+            .into_iter()
+            .min_by_key(|&(_, pos)| (grid.is_risky(pos), food.map_or(0, |food| grid.distance(pos, food))))
+            .map(|(dir, _)| dir)
+    }
+}
+
+/// Takes the shortest path to the food, but only when the snake could still
+/// reach its own tail after eating; otherwise it stalls for room.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BfsController;
+
+impl BfsController {
+    /// Whether, after walking `path` and growing at its end, the snake could
+    /// still follow its own tail, so it cannot have boxed itself in.
+    fn is_safe(sim: &Simulation, player: usize, path: &[GridPos]) -> bool {
+        let mut grid = Grid::new(sim, player);
+        let mut body: VecDeque<GridPos> = sim.players()[player].snake().cells().collect();
+        for (i, &pos) in path.iter().enumerate() {
+            body.push_front(pos);
+            if i + 1 < path.len() {
+                let tail = body.pop_back().unwrap_or(pos);
+                grid.set(tail, false);
+            }
+        }
+        for &pos in &body {
+            grid.set(pos, true);
+        }
+        let (head, tail) = (body[0], body[body.len() - 1]);
+        grid.path(head, tail).is_some()
+    }
+
+    /// Whether `player` is nearer to `food` than every other living head,
+    /// so it may step next to another snake to get there. On a tie the
+    /// lower player number goes first; otherwise two snakes chasing the
+    /// same food could shadow each other forever.
+    fn claims(sim: &Simulation, grid: &Grid, player: usize, food: GridPos) -> bool {
+        let distance = |i: usize| grid.distance(sim.players()[i].snake().head.pos, food);
+        let own = distance(player);
+        sim.players()
+            .iter()
+            .enumerate()
+            .filter(|&(i, p)| i != player && p.is_alive())
+            .all(|(i, _)| (own, player) < (distance(i), i))
+    }
+
+    /// Picks the move that keeps the tail in reach and the most room, for
+    /// when there is no safe way to the food.
+    fn stall(sim: &Simulation, player: usize) -> Option<Direction> {
+        let grid = Grid::new(sim, player);
+        let snake = sim.players()[player].snake();
+        let tail = snake.body.back().map_or(snake.head.pos, |seg| seg.pos);
+        grid.moves(sim, player)
+            .into_iter()
+            .max_by_key(|&(_, pos)| {
+                let mut after = Grid::new(sim, player);
+                after.set(snake.head.pos, true);
+                after.set(pos, true);
+                (after.path(pos, tail).is_some(), !grid.is_risky(pos), after.area(pos))
+            })
+            .map(|(dir, _)| dir)
+    }
+}
+
+impl SnakeController for BfsController {
+    fn decide(&mut self, sim: &Simulation, player: usize) -> Option<Direction> {
+        let grid = Grid::new(sim, player);
+        let head = sim.players()[player].snake().head.pos;
+        if let Some(food) = sim.food()
+            && let Some(path) = grid.path(head, food.pos)
+            && (!grid.is_risky(path[0]) || BfsController::claims(sim, &grid, player, food.pos))
+            && BfsController::is_safe(sim, player, &path)
+        {
+            return grid.direction_to(head, path[0]);
+        }
+        BfsController::stall(sim, player)
+    }
+}
+
+/// Free cells a shortcut must leave between the head and the tail, so the
+/// snake can still grow without running into itself.
+const SHORTCUT_MARGIN: usize = 3;
+
+/// Marks a cell that is not on the cycle in [`Cycle::order`].
+const OFF_CYCLE: usize = usize::MAX;
+
+/// How many spanning trees [`Cycle::build`] tries at most.
+const CYCLE_TRIES: usize = 32;
+
+/// A 2x2 block of cells in [`Cycle::build`], by its column and row.
+type Block = (i32, i32);
+
+/// A closed path through the free cells of the board, as visiting order.
+#[derive(Debug, Clone)]
+struct Cycle {
+    size: (i32, i32),
+    /// The walls it was built around.
+    walls: Vec<GridPos>,
+    /// Position of each cell in the cycle, indexed like [`Grid::blocked`],
+    /// or [`OFF_CYCLE`].
+    order: Vec<usize>,
+    cells: Vec<GridPos>,
+}
+
+impl Cycle {
+    /// Splits the board into 2x2 blocks, joins the largest group of
+    /// wall-free blocks along a spanning tree and walks round that tree,
+    /// which passes every cell of those blocks. Pairs of free cells left
+    /// over, next to walls or in an odd last row or column, are then
+    /// spliced in wherever the cycle runs alongside them.
+    ///
+    /// A grid cycle alternates between the two colours of a chessboard, so
+    /// a board with more free cells of one colour always keeps some cells
+    /// off the cycle.
+    fn build(size: (i32, i32), walls: &[GridPos]) -> Option<Cycle> {
+        let (w, h) = size;
+        let (bw, bh) = (w / 2, h / 2);
+        let cell = |x: i32, y: i32| (y * w + x) as usize;
+        let mut wall = vec![false; (w * h) as usize];
+        for pos in walls {
+            wall[cell(pos.x, pos.y)] = true;
+        }
+        let open = |bx: i32, by: i32| {
+            [(0, 0), (1, 0), (0, 1), (1, 1)].iter().all(|&(dx, dy)| !wall[cell(2 * bx + dx, 2 * by + dy)])
+        };
+
+        // The largest group of open blocks.
+        let neighbours = |(bx, by): Block| {
+            [(bx + 1, by), (bx - 1, by), (bx, by + 1), (bx, by - 1)]
+                .into_iter()
+                .filter(|&(x, y)| (0..bw).contains(&x) && (0..bh).contains(&y) && open(x, y))
+        };
+        let spread = |root: Block| {
+            let mut seen = vec![false; (bw * bh) as usize];
+            seen[(root.1 * bw + root.0) as usize] = true;
+            let (mut blocks, mut edges) = (vec![root], Vec::new());
+            let mut queue = VecDeque::from([root]);
+            while let Some(block) = queue.pop_front() {
+                for (x, y) in neighbours(block) {
+                    if !seen[(y * bw + x) as usize] {
+                        seen[(y * bw + x) as usize] = true;
+                        blocks.push((x, y));
+                        edges.push((block, (x, y)));
+                        queue.push_back((x, y));
+                    }
+                }
+            }
+            (blocks, edges)
+        };
+        let mut group: Vec<Block> = Vec::new();
+        let mut grouped = vec![false; (bw * bh) as usize];
+        for root in (0..bh).flat_map(|by| (0..bw).map(move |bx| (bx, by))) {
+            if grouped[(root.1 * bw + root.0) as usize] || !open(root.0, root.1) {
+                continue;
+            }
+            let (blocks, _) = spread(root);
+            for &(x, y) in &blocks {
+                grouped[(y * bw + x) as usize] = true;
+            }
+            if blocks.len() > group.len() {
+                group = blocks;
+            }
+        }
+        if group.is_empty() {
+            return None;
+        }
+        // Every root gives another tree. Keep the cycle that takes in the
+        // most cells, then the one with the shortest detours.
+        let mut best: Option<(usize, usize, Cycle)> = None;
+        for &root in group.iter().step_by(group.len().div_ceil(CYCLE_TRIES)) {
+            let (blocks, edges) = spread(root);
+            let cycle = Cycle::join(size, walls, &wall, &blocks, &edges);
+            let (missed, detour) = (usize::MAX - cycle.cells.len(), cycle.longest_detour(&wall));
+            if best.as_ref().is_none_or(|&(m, d, _)| (missed, detour) < (m, d)) {
+                best = Some((missed, detour, cycle));
+            }
+        }
+        best.map(|(_, _, cycle)| cycle)
+    }
+
+    /// Joins `blocks` along the tree `edges` and splices in what it can of
+    /// the free cells left over.
+    fn join(size: (i32, i32), walls: &[GridPos], wall: &[bool], blocks: &[Block], edges: &[(Block, Block)]) -> Cycle {
+        let (w, h) = size;
+        let cell = |x: i32, y: i32| (y * w + x) as usize;
+        // Each block starts as its own clockwise loop; every tree edge then
+        // swaps the two facing sides of its blocks, joining their loops.
+        let mut next = vec![OFF_CYCLE; (w * h) as usize];
+        let corners = |(bx, by): Block| {
+            let (x, y) = (2 * bx, 2 * by);
+            (cell(x, y), cell(x + 1, y), cell(x + 1, y + 1), cell(x, y + 1))
+        };
+        for &block in blocks {
+            let (tl, tr, br, bl) = corners(block);
+            next[tl] = tr;
+            next[tr] = br;
+            next[br] = bl;
+            next[bl] = tl;
+        }
+        for &(a, b) in edges {
+            let (a, b) = if b.0 < a.0 || b.1 < a.1 { (b, a) } else { (a, b) };
+            let ((_, a_tr, a_br, a_bl), (b_tl, b_tr, _, b_bl)) = (corners(a), corners(b));
+            if a.1 == b.1 {
+                next[a_tr] = b_tl;
+                next[b_bl] = a_br;
+            } else {
+                next[a_br] = b_tr;
+                next[b_tl] = a_bl;
+            }
+        }
+
+        let pos = |i: usize| GridPos::new(i as i32 % w, i as i32 / w);
+        let spare = |next: &[usize], pos: GridPos| {
+            (0..w).contains(&pos.x) && (0..h).contains(&pos.y) && !wall[cell(pos.x, pos.y)] && next[cell(pos.x, pos.y)] == OFF_CYCLE
+        };
+        loop {
+            let mut grew = false;
+            for a in 0..next.len() {
+                if next[a] == OFF_CYCLE {
+                    continue;
+                }
+                let (from, to) = (pos(a), pos(next[a]));
+                let (dx, dy) = (to.x - from.x, to.y - from.y);
+                for (sx, sy) in [(dy, dx), (-dy, -dx)] {
+                    let (c, d) = (GridPos::new(from.x + sx, from.y + sy), GridPos::new(to.x + sx, to.y + sy));
+                    if spare(&next, c) && spare(&next, d) {
+                        let (c, d) = (cell(c.x, c.y), cell(d.x, d.y));
+                        next[d] = next[a];
+                        next[c] = d;
+                        next[a] = c;
+                        grew = true;
+                        break;
+                    }
+                }
+            }
+            if !grew {
+                break;
+            }
+        }
+
+        let start = cell(2 * blocks[0].0, 2 * blocks[0].1);
+        let mut cells = vec![pos(start)];
+        let mut at = next[start];
+        while at != start {
+            cells.push(pos(at));
+            at = next[at];
+        }
+        let mut order = vec![OFF_CYCLE; (w * h) as usize];
+        for (i, pos) in cells.iter().enumerate() {
+            order[cell(pos.x, pos.y)] = i;
+        }
+        Cycle { size, walls: walls.to_vec(), order, cells }
+    }
+
+    /// How far along the cycle a detour to the worst placed free cell off
+    /// it has to skip: the shortest way between two cycle cells next to it.
+    fn longest_detour(&self, wall: &[bool]) -> usize {
+        let (w, h) = self.size;
+        let n = self.cells.len();
+        let off = (0..h)
+            .flat_map(|y| (0..w).map(move |x| GridPos::new(x, y)))
+            .filter(|&pos| !wall[(pos.y * w + pos.x) as usize] && !self.contains(pos));
+        off.map(|pos| {
+            let near: Vec<usize> = Direction::ALL
+                .into_iter()
+                .filter_map(|dir| GridPos::new_from_move(pos, dir, self.size, WrapMode::Off))
+                .filter(|&pos| self.contains(pos))
+                .map(|pos| self.index(pos))
+                .collect();
+            let gaps = near.iter().flat_map(|&i| near.iter().filter(move |&&j| j != i).map(move |&j| (j + n - i) % n));
+            gaps.map(|gap| gap.min(n - gap)).min().unwrap_or(n)
+        })
+        .max()
+        .unwrap_or(0)
+    }
+
+    fn contains(&self, pos: GridPos) -> bool {
+        self.order[(pos.y * self.size.0 + pos.x) as usize] != OFF_CYCLE
+    }
+
+    fn index(&self, pos: GridPos) -> usize {
+        self.order[(pos.y * self.size.0 + pos.x) as usize]
+    }
+
+    /// The cell after `pos` on the cycle.
+    fn next(&self, pos: GridPos) -> GridPos {
+        self.cells[(self.index(pos) + 1) % self.cells.len()]
+    }
+
+    /// Runs the cycle the other way round.
+    fn reverse(&mut self) {
+        self.cells.reverse();
+        let w = self.size.0;
+        for (i, pos) in self.cells.iter().enumerate() {
+            self.order[(pos.y * w + pos.x) as usize] = i;
+        }
+    }
+
+    /// Steps along the cycle from `a` to `b`.
+    fn distance(&self, a: GridPos, b: GridPos) -> usize {
+        (self.index(b) + self.cells.len() - self.index(a)) % self.cells.len()
+    }
+}
+
+/// A way to food off the cycle for a snake with `room` free cells ahead
+/// of its head on the cycle: through free cells among those and off the
+/// cycle to the food, then on through cells off the cycle back onto it,
+/// past every cycle cell used on the way. The cycle cells passed over are
+/// left behind the head, so the body never lies ahead of it.
+fn detour(cycle: &Cycle, grid: &Grid, head: GridPos, food: GridPos, room: usize) -> Option<Vec<GridPos>> {
+    let ahead = |pos: GridPos| (1..room).contains(&cycle.distance(head, pos));
+    let there = grid.search(head, |pos| grid.is_free(pos) && (!cycle.contains(pos) || ahead(pos)), |pos| pos == food)?;
+    let skipped = there
+        .iter()
+        .filter(|&&pos| cycle.contains(pos))
+        .map(|&pos| cycle.distance(head, pos))
+        .max()
+        .unwrap_or(0);
+    let goal = |pos: GridPos| {
+        cycle.contains(pos) && grid.is_free(pos) && ahead(pos) && cycle.distance(head, pos) > skipped && !there.contains(&pos)
+    };
+    let back = grid.search(
+        food,
+        |pos| goal(pos) || (!cycle.contains(pos) && grid.is_free(pos) && !there.contains(&pos)),
+        goal,
+    )?;
+    Some(there.into_iter().chain(back).collect())
+}
+
+/// Follows a Hamiltonian cycle, which fills the board without ever dying,
+/// taking shortcuts towards the food while the snake is short. The cycle is
+/// run whichever way round the snake starts out facing.
+///
+/// The cycle is built around the level's walls. Food on a cell the cycle
+/// cannot take in is fetched by a [`detour`] whenever the snake leaves
+/// enough room for one. When it does not, for two laps in a row, or while
+/// the head is off the cycle for another reason, the snake plays like
+/// [`BfsController`].
+#[derive(Debug, Clone, Default)]
+pub struct HamiltonianController {
+    cycle: Option<Cycle>,
+    /// The rest of the detour being taken, last step first.
+    detour: Vec<GridPos>,
+    /// The snake's length as of the last tick, and how many ticks it has
+    /// had it.
+    length: usize,
+    hungry: usize,
+}
+
+impl SnakeController for HamiltonianController {
+    fn decide(&mut self, sim: &Simulation, player: usize) -> Option<Direction> {
+        let level = sim.level();
+        if self.cycle.as_ref().is_none_or(|cycle| cycle.size != level.size || cycle.walls != level.obstacles) {
+            self.cycle = Cycle::build(level.size, &level.obstacles);
+            self.detour.clear();
+        }
+        let Some(cycle) = &mut self.cycle else {
+            return BfsController.decide(sim, player);
+        };
+
+        let grid = Grid::new(sim, player);
+        let snake = sim.players()[player].snake();
+        let head = snake.head.pos;
+        if snake.len() != self.length {
+            (self.length, self.hungry) = (snake.len(), 0);
+        }
+        self.hungry += 1;
+
+        if let Some(&step) = self.detour.last() {
+            if grid.is_free(step)
+                && !grid.is_risky(step)
+                && let Some(dir) = grid.direction_to(head, step)
+            {
+                self.detour.pop();
+                return Some(dir);
+            }
+            self.detour.clear();
+        }
+        if !cycle.contains(head) || self.hungry > 2 * cycle.cells.len() {
+            return BfsController.decide(sim, player);
+        }
+
+        // A snake always faces away from its neck, so it can only be on
+        // the cycle going the way where the neck is behind it.
+        if snake.body.front().is_some_and(|neck| cycle.next(head) == neck.pos) {
+            cycle.reverse();
+        }
+        // How far the head can go along the cycle before it meets the body.
+        let room = snake
+            .body
+            .iter()
+            .filter(|seg| cycle.contains(seg.pos))
+            .map(|seg| cycle.distance(head, seg.pos))
+            .min()
+            .unwrap_or(cycle.cells.len());
+
+        // Shortcuts only skip free cells ahead, so the body always stays
+        // behind the head on the cycle.
+        if let Some(food) = sim.food()
+            && cycle.contains(food.pos)
+            && snake.len() * 2 < cycle.cells.len()
+        {
+            let to_food = cycle.distance(head, food.pos);
+            let shortcut = grid
+                .moves(sim, player)
+                .into_iter()
+                .filter(|&(_, pos)| cycle.contains(pos))
+                .filter(|&(_, pos)| {
+                    let d = cycle.distance(head, pos);
+                    d <= to_food && d + SHORTCUT_MARGIN < room && !grid.is_risky(pos)
+                })
+                .max_by_key(|&(_, pos)| cycle.distance(head, pos));
+            if let Some((dir, _)) = shortcut {
+                return Some(dir);
+            }
+        }
+
+        if let Some(food) = sim.food()
+            && !cycle.contains(food.pos)
+            && let Some(path) = detour(cycle, &grid, head, food.pos, room)
+            && !grid.is_risky(path[0])
+        {
+            self.detour = path.into_iter().rev().collect();
+            let step = self.detour.pop()?;
+            return grid.direction_to(head, step);
+        }
+
+        let next = cycle.next(head);
+        if grid.is_free(next) && !grid.is_risky(next) {
+            return grid.direction_to(head, next);
+        }
+        BfsController.decide(sim, player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+    use crate::sim::{Ate, TickOutcome};
+
+    /// An open 10x8 board without wrapping, the snake starting at `spawn`.
+    fn open_level(spawn: GridPos, direction: Direction) -> Level {
+        let mut level = Level::basic_levels().remove(0);
+        level.size = (10, 8);
+        level.wrap = WrapMode::Off;
+        level.spawn = spawn;
+        level.direction = direction;
+        level
+    }
+
+    fn play(level: Level, seed: u64, controller: &mut dyn SnakeController, max_ticks: u32) -> Simulation {
+        let mut sim = Simulation::with_players(level, seed, 1);
+        while !sim.is_over() && sim.ticks() < max_ticks {
+            let turn = controller.decide(&sim, 0);
+            sim.step(turn);
+        }
+        sim
+    }
+
+    #[test]
+    fn hamiltonian_fills_an_open_board() {
+        for seed in 0..3 {
+            let level = open_level(GridPos::new(4, 2), Direction::Right);
+            let sim = play(level, seed, &mut HamiltonianController::default(), 20_000);
+            assert!(sim.is_won(), "seed {}: died of {:?} at length {}", seed, sim.death(), sim.snake().len());
+        }
+    }
+
+    #[test]
+    fn hamiltonian_follows_the_cycle_against_its_direction() {
+        // Row 2 of the cycle runs one way, so one of these snakes starts out
+        // facing against it.
+        for dir in [Direction::Left, Direction::Right] {
+            let level = open_level(GridPos::new(4, 2), dir);
+            let mut controller = HamiltonianController::default();
+            let mut sim = Simulation::with_players(level, 1, 1);
+            while !sim.is_over() {
+                let turn = controller.decide(&sim, 0);
+                sim.step(turn);
+
+                // The body lies along the cycle from the tail up to the head.
+                let cycle = controller.cycle.as_ref().unwrap();
+                let cells: Vec<GridPos> = sim.snake().cells().collect();
+                let along: usize = cells.windows(2).map(|pair| cycle.distance(pair[1], pair[0])).sum();
+                assert_eq!(along, cycle.distance(cells[cells.len() - 1], cells[0]), "{:?} tick {}", dir, sim.ticks());
+            }
+            assert!(sim.is_won(), "{:?}: died of {:?} at length {}", dir, sim.death(), sim.snake().len());
+        }
+    }
+
+    #[test]
+    fn hamiltonian_fills_a_walled_board() {
+        // A pillar in the middle, so every free cell fits on the cycle.
+        let walls = [(4, 2), (5, 2), (4, 3), (5, 3), (4, 4), (5, 4), (4, 5), (5, 5)];
+        let mut level = open_level(GridPos::new(1, 2), Direction::Down);
+        level.obstacles = walls.into_iter().map(|(x, y)| GridPos::new(x, y)).collect();
+        let cycle = Cycle::build(level.size, &level.obstacles).unwrap();
+        assert_eq!(cycle.cells.len(), 80 - walls.len());
+        for seed in 0..3 {
+            let sim = play(level.clone(), seed, &mut HamiltonianController::default(), 20_000);
+            assert!(sim.is_won(), "seed {}: died of {:?} at length {}", seed, sim.death(), sim.snake().len());
+        }
+    }
+
+    #[test]
+    fn hamiltonian_detours_to_food_off_its_cycle() {
+        // One wall leaves more free cells of one chessboard colour, so one
+        // cell always stays off the cycle.
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assert/levels");
+        let level = Level::load_dir(&dir).0.into_iter().find(|level| level.name == "Medium").unwrap();
+        let cycle = Cycle::build(level.size, &level.obstacles).unwrap();
+        let free = (level.size.0 * level.size.1) as usize - level.obstacles.len();
+        assert_eq!(cycle.cells.len(), free - 1);
+
+        let sim = play(level, 1, &mut HamiltonianController::default(), 100_000);
+        assert!(sim.snake().len() + 3 >= free, "died of {:?} at length {}", sim.death(), sim.snake().len());
+    }
+
+    #[test]
+    fn bfs_never_crashes_while_it_has_a_free_move() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assert/levels");
+        let levels = Level::basic_levels().into_iter().chain(Level::load_dir(&dir).0);
+        for level in levels {
+            for seed in 0..2 {
+                let mut sim = Simulation::with_players(level.clone(), seed, 1);
+                while !sim.is_over() && sim.ticks() < 1000 {
+                    let free = Grid::new(&sim, 0).moves(&sim, 0).len();
+                    let turn = BfsController.decide(&sim, 0);
+                    if let TickOutcome::Ate(death @ (Ate::Wall | Ate::Obstacle | Ate::Itself)) = sim.step(turn) {
+                        assert_eq!(free, 0, "{} seed {}: {:?} at tick {}", level.name, seed, death, sim.ticks());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -27,7 +27,7 @@ impl BgmManager {
         println!("true");
         music.set_repeat(repeat);
         music.set_volume(volume);
-        let _ = music.play(ctx)?;
+        music.play(ctx)?;
         Ok(Self {
             music: Some(music),
             state: BgmState::Play
@@ -42,25 +42,25 @@ impl BgmManager {
         self.music = None;
     }
 
-    fn resume_bgm(&mut self, ctx: &mut Context) {
-        if let Some(m) = &mut self.music {
-            if let BgmState::Pause = self.state {
-                self.state = BgmState::Play;
-                let _ = m.resume();
-            }
+    fn resume_bgm(&mut self) {
+        if let Some(m) = &mut self.music
+            && let BgmState::Pause = self.state
+        {
+            self.state = BgmState::Play;
+            m.resume();
         }
     }
 
-    fn pause_bgm(&mut self, ctx: &mut Context) {
+    fn pause_bgm(&mut self) {
         if let Some(m) = &mut self.music {
             self.state = BgmState::Pause;
-            let _ = m.pause();
+            m.pause();
         }
     }
 
     fn replay_bgm(&mut self, ctx: &mut Context) {
         if let Some(m) = &mut self.music {
-            let _ = m.set_start(Duration::ZERO);
+            m.set_start(Duration::ZERO);
             let _ = m.play(ctx);
         }
     }
@@ -87,6 +87,14 @@ impl AudioManager {
         Ok(())
     }
 
+    pub fn pause_bgm(&mut self) {
+        self.bgm.pause_bgm();
+    }
+
+    pub fn resume_bgm(&mut self) {
+        self.bgm.resume_bgm();
+    }
+
     pub fn stop_bgm(&mut self, ctx: &mut Context) {
         self.bgm.stop_bgm(ctx);
     }
@@ -102,6 +110,10 @@ impl AudioManager {
         Ok(())
     }
 
+    pub fn has_sfx(&self, name: &str) -> bool {
+        self.sfx.contains_key(name)
+    }
+
     pub fn play_sfx(&mut self, name: &str, ctx: &mut Context) {
         if let Some(sfx) = self.sfx.get_mut(name) {
             let _ = sfx.play(ctx);
@@ -0,0 +1,278 @@
+//! Plays many seeded games between the built-in AIs without opening a
+//! window and prints how they did, to compare AI changes and level
+//! difficulty.
+
+use std::{env, path::PathBuf, process, thread, time::Instant};
+
+use snake::controller::ControllerKind;
+use snake::level::Level;
+use snake::tournament::{self, Matchup, Summary};
+
+const USAGE: &str = "\
+usage: snake-sim [options]
+
+  --games <n>       games per level and matchup (default 100)
+  --seed <n>        seed of the first game; the others count up (default 0)
+  --ai <list>       comma-separated AIs: greedy, bfs, hamiltonian (default all);
+                    hamiltonian may miss a few cells on boards with walls
+  --level <list>    comma-separated level names or level files (default all)
+  --levels <dir>    folder to load levels from (default the game's own)
+  --versus          play every pair of AIs against each other instead
+  --max-ticks <n>   stop a game after this many ticks (default 100000)
+  --threads <n>     worker threads (default one per core)
+  --json            print JSON instead of a table";
+
+#[derive(Debug, Clone)]
+struct Options {
+    games: usize,
+    seed: u64,
+    ais: Vec<ControllerKind>,
+    levels: Vec<String>,
+    levels_dir: PathBuf,
+    versus: bool,
+    max_ticks: u32,
+    threads: usize,
+    json: bool,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    value
+        .as_deref()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} expects a number", flag))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        games: 100,
+        seed: 0,
+        ais: vec![ControllerKind::Greedy, ControllerKind::Bfs, ControllerKind::Hamiltonian],
+        levels: Vec::new(),
+        levels_dir: match env::var("CARGO_MANIFEST_DIR") {
+            Ok(manifest_dir) => PathBuf::from(manifest_dir).join("assert").join("levels"),
+            Err(_) => PathBuf::from("./resources/levels"),
+        },
+        versus: false,
+        max_ticks: 100_000,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        json: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || value.clone().or_else(|| args.next());
+        match flag.as_str() {
+            "--games" => options.games = parse_number(&flag, value())?,
+            "--seed" => options.seed = parse_number(&flag, value())?,
+            "--max-ticks" => options.max_ticks = parse_number(&flag, value())?,
+            "--threads" => options.threads = parse_number::<usize>(&flag, value())?.max(1),
+            "--ai" => {
+                let list = value().ok_or("--ai expects a list of AIs")?;
+                options.ais = list
+                    .split(',')
+                    .map(|name| {
+                        ControllerKind::parse(name.trim())
+                            .filter(|&kind| kind != ControllerKind::Keyboard)
+                            .ok_or_else(|| format!("unknown AI `{}`", name))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--level" => {
+                let list = value().ok_or("--level expects a list of levels")?;
+                options.levels = list.split(',').map(|name| name.trim().to_string()).collect();
+            }
+            "--levels" => options.levels_dir = PathBuf::from(value().ok_or("--levels expects a folder")?),
+            "--versus" => options.versus = true,
+            "--json" => options.json = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown option `{}`", flag)),
+        }
+    }
+    Ok(options)
+}
+
+/// The levels named on the command line, looked up by name among the loaded
+/// levels first and then as files, or every loaded level.
+fn pick_levels(options: &Options) -> Result<Vec<Level>, String> {
+    let (mut loaded, errors) = Level::load_dir(&options.levels_dir);
+    for err in &errors {
+        eprintln!("{}", err);
+    }
+    if loaded.is_empty() {
+        loaded = Level::basic_levels();
+    }
+    if options.levels.is_empty() {
+        return Ok(loaded);
+    }
+    options
+        .levels
+        .iter()
+        .map(|name| match loaded.iter().find(|level| level.name.eq_ignore_ascii_case(name)) {
+            Some(level) => Ok(level.clone()),
+            None => Level::load(&PathBuf::from(name)).map_err(|err| err.to_string()),
+        })
+        .collect()
+}
+
+fn matchups(options: &Options, levels: &[Level]) -> Vec<Matchup> {
+    let mut pairings = Vec::new();
+    if options.versus {
+        for (i, &first) in options.ais.iter().enumerate() {
+            for &second in &options.ais[i..] {
+                pairings.push(vec![first, second]);
+            }
+        }
+    } else {
+        pairings.extend(options.ais.iter().map(|&kind| vec![kind]));
+    }
+    levels
+        .iter()
+        .flat_map(|level| {
+            pairings.iter().map(|controllers| Matchup { level: level.clone(), controllers: controllers.clone() })
+        })
+        .collect()
+}
+
+fn percent(count: usize, games: usize) -> String {
+    format!("{:.0}%", 100.0 * count as f64 / games.max(1) as f64)
+}
+
+fn print_table(summaries: &[Summary], versus: bool) {
+    if versus {
+        println!(
+            "{:<12} {:<12} {:<12} {:>6} {:>8} {:>8} {:>6} {:>9} {:>9} {:>8} {:>8}",
+            "level", "p1", "p2", "games", "p1 wins", "p2 wins", "draws", "p1 score", "p2 score", "ticks", "timeouts"
+        );
+        for s in summaries {
+            let (p1, p2) = (&s.players[0], &s.players[1]);
+            println!(
+                "{:<12} {:<12} {:<12} {:>6} {:>8} {:>8} {:>6} {:>9.1} {:>9.1} {:>8.0} {:>8}",
+                s.level,
+                p1.controller.name(),
+                p2.controller.name(),
+                s.games,
+                percent(p1.wins, s.games),
+                percent(p2.wins, s.games),
+                percent(s.draws, s.games),
+                p1.score.mean,
+                p2.score.mean,
+                s.ticks.mean,
+                percent(s.timeouts, s.games),
+            );
+        }
+        return;
+    }
+
+    println!(
+        "{:<12} {:<12} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>7} {:>8}  deaths",
+        "level", "ai", "games", "score", "median", "length", "ticks", "median", "filled", "timeouts"
+    );
+    for s in summaries {
+        let p = &s.players[0];
+        let deaths: Vec<String> =
+            p.deaths.iter().map(|&(cause, count)| format!("{} {}", cause, percent(count, s.games))).collect();
+        println!(
+            "{:<12} {:<12} {:>6} {:>8.1} {:>8.1} {:>8.1} {:>8.0} {:>8.0} {:>7} {:>8}  {}",
+            s.level,
+            p.controller.name(),
+            s.games,
+            p.score.mean,
+            p.score.median,
+            p.length.mean,
+            s.ticks.mean,
+            s.ticks.median,
+            percent(s.won, s.games),
+            percent(s.timeouts, s.games),
+            deaths.join(", "),
+        );
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn print_json(summaries: &[Summary]) {
+    let matchups: Vec<String> = summaries
+        .iter()
+        .map(|s| {
+            let players: Vec<String> = s
+                .players
+                .iter()
+                .map(|p| {
+                    let deaths: Vec<String> =
+                        p.deaths.iter().map(|&(cause, count)| format!("{}: {}", json_string(cause), count)).collect();
+                    format!(
+                        "{{\"ai\": {}, \"wins\": {}, \"score\": {{\"mean\": {:.3}, \"median\": {}}}, \
+                         \"length\": {{\"mean\": {:.3}, \"median\": {}}}, \"deaths\": {{{}}}}}",
+                        json_string(p.controller.name()),
+                        p.wins,
+                        p.score.mean,
+                        p.score.median,
+                        p.length.mean,
+                        p.length.median,
+                        deaths.join(", "),
+                    )
+                })
+                .collect();
+            format!(
+                "  {{\"level\": {}, \"games\": {}, \"ticks\": {{\"mean\": {:.3}, \"median\": {}}}, \"filled\": {}, \
+                 \"timeouts\": {}, \"draws\": {}, \"players\": [{}]}}",
+                json_string(&s.level),
+                s.games,
+                s.ticks.mean,
+                s.ticks.median,
+                s.won,
+                s.timeouts,
+                s.draws,
+                players.join(", "),
+            )
+        })
+        .collect();
+    println!("[\n{}\n]", matchups.join(",\n"));
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|err| {
+        eprintln!("snake-sim: {}\n\n{}", err, USAGE);
+        process::exit(2);
+    });
+    let levels = pick_levels(&options).unwrap_or_else(|err| {
+        eprintln!("snake-sim: {}", err);
+        process::exit(1);
+    });
+
+    let matchups = matchups(&options, &levels);
+    let started = Instant::now();
+    let records = tournament::run(&matchups, options.games, options.seed, options.max_ticks, options.threads);
+    let summaries: Vec<Summary> =
+        matchups.iter().zip(&records).map(|(matchup, records)| Summary::new(matchup, records)).collect();
+
+    if options.json {
+        print_json(&summaries);
+    } else {
+        print_table(&summaries, options.versus);
+    }
+    eprintln!(
+        "{} games on {} threads in {:.1}s",
+        matchups.len() * options.games,
+        options.threads,
+        started.elapsed().as_secs_f32()
+    );
+}
@@ -0,0 +1,115 @@
+use crate::ai::{BfsController, GreedyController, HamiltonianController};
+use crate::sim::{Direction, Simulation, TurnQueue};
+
+/// Decides where a snake goes, once per tick.
+///
+/// Controllers only get to look at the game; the turn they return is applied
+/// by whoever runs it, so every controller can be recorded in a replay the
+/// same way.
+pub trait SnakeController {
+    /// Picks the turn for `player`'s snake before the next tick, or `None` to
+    /// keep going the way it is heading.
+    fn decide(&mut self, sim: &Simulation, player: usize) -> Option<Direction>;
+
+    /// A turn the player asked for on the keyboard. Only the keyboard
+    /// controller listens; the AIs ignore it.
+    fn press(&mut self, _dir: Direction) {}
+}
+
+/// Asks the controller of every living snake for its turn and applies it,
+/// player `i` being driven by `controllers[i]`.
+pub fn steer_all(sim: &mut Simulation, controllers: &mut [Box<dyn SnakeController>]) {
+    for (player, controller) in controllers.iter_mut().enumerate() {
+        if sim.players().get(player).is_some_and(|p| p.is_alive())
+            && let Some(dir) = controller.decide(sim, player)
+        {
+            sim.steer_player(player, dir);
+        }
+    }
+}
+
+/// Hands turns pressed on the keyboard to the snake one per tick, for games
+/// where turns travel one per tick and cannot be queued on the snake right
+/// away, as in a networked game. Local games queue presses on the snake
+/// itself, so there this controller never turns.
+#[derive(Debug, Clone, Default)]
+pub struct KeyboardController {
+    pending: TurnQueue,
+    /// Where the snake goes once the last turn handed out is made, to check
+    /// presses against. Turns may be made some ticks after they are handed
+    /// out, so this can be ahead of the snake.
+    heading: Option<Direction>,
+}
+
+impl KeyboardController {
+    /// Keeps up to `depth` presses for the coming ticks; the default keeps
+    /// [`TURN_BUFFER`](crate::sim::TURN_BUFFER).
+    pub fn new(depth: usize) -> Self {
+        KeyboardController { pending: TurnQueue::new(depth.max(1)), heading: None }
+    }
+}
+
+impl SnakeController for KeyboardController {
+    fn decide(&mut self, sim: &Simulation, player: usize) -> Option<Direction> {
+        let heading = self.heading.unwrap_or(sim.players().get(player)?.snake().dir);
+        // Presses made before the heading was known may not fit it.
+        let turn = std::iter::from_fn(|| self.pending.pop()).find(|&dir| dir != heading && dir != heading.inverse());
+        self.heading = Some(turn.unwrap_or(heading));
+        turn
+    }
+
+    fn press(&mut self, dir: Direction) {
+        self.pending.push(dir, self.heading);
+    }
+}
+
+/// The built-in controllers, for picking one in a menu or by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerKind {
+    Keyboard,
+    Greedy,
+    Bfs,
+    Hamiltonian,
+}
+
+impl ControllerKind {
+    pub const ALL: [ControllerKind; 4] = [
+        ControllerKind::Keyboard,
+        ControllerKind::Greedy,
+        ControllerKind::Bfs,
+        ControllerKind::Hamiltonian,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        ControllerKind::ALL.into_iter().find(|kind| kind.name() == s)
+    }
+
+    /// Short name used on the command line and in the HUD.
+    pub fn name(self) -> &'static str {
+        match self {
+            ControllerKind::Keyboard => "keyboard",
+            ControllerKind::Greedy => "greedy",
+            ControllerKind::Bfs => "bfs",
+            ControllerKind::Hamiltonian => "hamiltonian",
+        }
+    }
+
+    /// Name shown in menus.
+    pub fn label(self) -> &'static str {
+        match self {
+            ControllerKind::Keyboard => "Human",
+            ControllerKind::Greedy => "Greedy AI",
+            ControllerKind::Bfs => "Path AI",
+            ControllerKind::Hamiltonian => "Cycle AI",
+        }
+    }
+
+    pub fn build(self) -> Box<dyn SnakeController> {
+        match self {
+            ControllerKind::Keyboard => Box::new(KeyboardController::default()),
+            ControllerKind::Greedy => Box::new(GreedyController),
+            ControllerKind::Bfs => Box::new(BfsController),
+            ControllerKind::Hamiltonian => Box::new(HamiltonianController::default()),
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use ggez::input::keyboard::KeyCode;
+
+use crate::sim::Direction;
+
+const HEADER: &str = "# snake controls v1";
+
+/// Most keys one action can be bound to.
+pub const MAX_BINDINGS: usize = 3;
+
+/// Where an action is listened for. Two actions may share a key only if
+/// they are never listened for in the same place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// A running game.
+    Game,
+    /// The menus, including the pause and game over screens.
+    Menu,
+}
+
+/// Something the player can do with a key, whichever key that is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    P2Up,
+    P2Down,
+    P2Left,
+    P2Right,
+    Pause,
+    Restart,
+    Autopilot,
+    Confirm,
+    Back,
+}
+
+impl Action {
+    pub const ALL: [Action; 13] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::P2Up,
+        Action::P2Down,
+        Action::P2Left,
+        Action::P2Right,
+        Action::Pause,
+        Action::Restart,
+        Action::Autopilot,
+        Action::Confirm,
+        Action::Back,
+    ];
+
+    /// Name used in the controls file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveUp => "move-up",
+            Action::MoveDown => "move-down",
+            Action::MoveLeft => "move-left",
+            Action::MoveRight => "move-right",
+            Action::P2Up => "p2-up",
+            Action::P2Down => "p2-down",
+            Action::P2Left => "p2-left",
+            Action::P2Right => "p2-right",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Autopilot => "autopilot",
+            Action::Confirm => "confirm",
+            Action::Back => "back",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveUp => "Up",
+            Action::MoveDown => "Down",
+            Action::MoveLeft => "Left",
+            Action::MoveRight => "Right",
+            Action::P2Up => "Player 2 up",
+            Action::P2Down => "Player 2 down",
+            Action::P2Left => "Player 2 left",
+            Action::P2Right => "Player 2 right",
+            Action::Pause => "Pause",
+            Action::Restart => "Restart",
+            Action::Autopilot => "Autopilot",
+            Action::Confirm => "Confirm",
+            Action::Back => "Back",
+        }
+    }
+
+    /// The first player's moves also move through the menus.
+    pub fn scopes(self) -> &'static [Scope] {
+        match self {
+            Action::MoveUp | Action::MoveDown | Action::MoveLeft | Action::MoveRight => &[Scope::Game, Scope::Menu],
+            Action::P2Up | Action::P2Down | Action::P2Left | Action::P2Right => &[Scope::Game],
+            Action::Pause | Action::Autopilot => &[Scope::Game],
+            Action::Restart | Action::Confirm | Action::Back => &[Scope::Menu],
+        }
+    }
+
+    /// The snake and direction a move action steers.
+    pub fn direction(self) -> Option<(usize, Direction)> {
+        match self {
+            Action::MoveUp => Some((0, Direction::Up)),
+            Action::MoveDown => Some((0, Direction::Down)),
+            Action::MoveLeft => Some((0, Direction::Left)),
+            Action::MoveRight => Some((0, Direction::Right)),
+            Action::P2Up => Some((1, Direction::Up)),
+            Action::P2Down => Some((1, Direction::Down)),
+            Action::P2Left => Some((1, Direction::Left)),
+            Action::P2Right => Some((1, Direction::Right)),
+            _ => None,
+        }
+    }
+
+    fn defaults(self) -> Vec<KeyCode> {
+        match self {
+            Action::MoveUp => vec![KeyCode::Up],
+            Action::MoveDown => vec![KeyCode::Down],
+            Action::MoveLeft => vec![KeyCode::Left],
+            Action::MoveRight => vec![KeyCode::Right],
+            Action::P2Up => vec![KeyCode::W],
+            Action::P2Down => vec![KeyCode::S],
+            Action::P2Left => vec![KeyCode::A],
+            Action::P2Right => vec![KeyCode::D],
+            Action::Pause => vec![KeyCode::Escape],
+            Action::Restart => vec![KeyCode::R],
+            Action::Autopilot => vec![KeyCode::Tab],
+            Action::Confirm => vec![KeyCode::Return, KeyCode::NumpadEnter],
+            Action::Back => vec![KeyCode::Escape],
+        }
+    }
+
+    fn overlaps(self, other: Action) -> bool {
+        self.scopes().iter().any(|scope| other.scopes().contains(scope))
+    }
+}
+
+/// Keys that can be bound. The rest, like media and launcher keys, are
+/// left to the system.
+const KEYS: &[KeyCode] = &[
+    KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5,
+    KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9, KeyCode::Key0,
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G,
+    KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N,
+    KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::Escape, KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5,
+    KeyCode::F6, KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::Insert, KeyCode::Home, KeyCode::Delete, KeyCode::End, KeyCode::PageDown, KeyCode::PageUp,
+    KeyCode::Left, KeyCode::Up, KeyCode::Right, KeyCode::Down,
+    KeyCode::Back, KeyCode::Return, KeyCode::Space, KeyCode::Tab,
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+    KeyCode::NumpadAdd, KeyCode::NumpadDivide, KeyCode::NumpadDecimal, KeyCode::NumpadEnter,
+    KeyCode::NumpadMultiply, KeyCode::NumpadSubtract,
+    KeyCode::Apostrophe, KeyCode::Backslash, KeyCode::Comma, KeyCode::Equals, KeyCode::Grave,
+    KeyCode::LBracket, KeyCode::RBracket, KeyCode::Minus, KeyCode::Period, KeyCode::Semicolon,
+    KeyCode::Slash, KeyCode::LAlt, KeyCode::RAlt, KeyCode::LControl, KeyCode::RControl,
+    KeyCode::LShift, KeyCode::RShift,
+];
+
+/// Name of `key` in the controls file.
+fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    KEYS.iter().copied().find(|&key| key_name(key) == name)
+}
+
+/// How `key` is shown on screen.
+pub fn key_label(key: KeyCode) -> String {
+    match key {
+        KeyCode::Return => "Enter".to_string(),
+        KeyCode::Back => "Backspace".to_string(),
+        KeyCode::Escape => "Esc".to_string(),
+        KeyCode::NumpadEnter => "Keypad Enter".to_string(),
+        key => key_name(key).trim_start_matches("Key").to_string(),
+    }
+}
+
+/// Which keys trigger which actions, stored as a tab-separated text file
+/// with one action and its keys per line.
+///
+/// A missing file means the default keys; lines that cannot be read are
+/// skipped and reported on stderr, leaving those actions at their
+/// defaults.
+#[derive(Debug, Clone)]
+pub struct Controls {
+    path: PathBuf,
+    /// The keys of each action, in the order of [`Action::ALL`].
+    bindings: Vec<Vec<KeyCode>>,
+}
+
+impl Controls {
+    pub fn new(path: &Path) -> Self {
+        Controls { path: path.to_path_buf(), bindings: Action::ALL.iter().map(|action| action.defaults()).collect() }
+    }
+
+    /// Reads the key map from `path`. Never fails: problems are reported
+    /// and the defaults fill in.
+    pub fn load(path: &Path) -> Self {
+        let mut controls = Controls::new(path);
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return controls,
+            Err(err) => {
+                eprintln!("{}: cannot read controls, using the defaults: {}", path.display(), err);
+                return controls;
+            }
+        };
+
+        for (no, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let action = fields.next().and_then(|name| Action::ALL.into_iter().find(|a| a.name() == name));
+            let keys: Option<Vec<KeyCode>> = fields.map(parse_key).collect();
+            match (action, keys) {
+                (Some(action), Some(keys)) if !keys.is_empty() && keys.len() <= MAX_BINDINGS => {
+                    controls.bindings[action as usize] = keys;
+                }
+                _ => eprintln!("{}:{}: skipping unreadable controls line", path.display(), no + 1),
+            }
+        }
+        // A hand-edited file may bind one key twice; the later action
+        // loses it, or goes back to those of its defaults that are still
+        // free if it has nothing left. If even those are taken, the whole
+        // file is dropped, as the defaults never conflict.
+        for (i, action) in Action::ALL.into_iter().enumerate() {
+            let taken = |key: &KeyCode| {
+                Action::ALL[..i].iter().any(|&earlier| action.overlaps(earlier) && controls.is(*key, earlier))
+            };
+            let mut kept: Vec<KeyCode> = controls.bindings[i].iter().copied().filter(|key| !taken(key)).collect();
+            if kept.is_empty() {
+                eprintln!("{}: keys of {} are taken, using its defaults", path.display(), action.name());
+                kept = action.defaults().into_iter().filter(|key| !taken(key)).collect();
+            }
+            if kept.is_empty() {
+                eprintln!("{}: defaults of {} are taken too, using the default controls", path.display(), action.name());
+                return Controls::new(path);
+            }
+            controls.bindings[i] = kept;
+        }
+        controls
+    }
+
+    /// Writes the key map next to the target first and then renames, so a
+    /// crash halfway through cannot leave a truncated file behind.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = format!("{}\n", HEADER);
+        for action in Action::ALL {
+            let keys: Vec<String> = self.keys(action).iter().map(|&key| key_name(key)).collect();
+            out.push_str(&format!("{}\t{}\n", action.name(), keys.join("\t")));
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        &self.bindings[action as usize]
+    }
+
+    /// All keys of `action`, as shown on the controls screen.
+    pub fn describe(&self, action: Action) -> String {
+        let names: Vec<String> = self.keys(action).iter().map(|&key| key_label(key)).collect();
+        names.join(" / ")
+    }
+
+    /// The first key of `action`, for hints like "Esc: back".
+    pub fn hint(&self, action: Action) -> String {
+        self.keys(action).first().map_or_else(String::new, |&key| key_label(key))
+    }
+
+    /// The action `key` triggers in `scope`, if any.
+    pub fn action(&self, key: KeyCode, scope: Scope) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|&action| action.scopes().contains(&scope) && self.keys(action).contains(&key))
+    }
+
+    /// Whether `key` triggers `action`.
+    pub fn is(&self, key: KeyCode, action: Action) -> bool {
+        self.keys(action).contains(&key)
+    }
+
+    /// Another action that `key` already triggers where `action` is
+    /// listened for.
+    pub fn conflict(&self, action: Action, key: KeyCode) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|&other| other != action && action.overlaps(other) && self.keys(other).contains(&key))
+    }
+
+    /// Adds `key` to `action`'s keys, or says why it cannot be.
+    pub fn bind(&mut self, action: Action, key: KeyCode) -> Result<(), String> {
+        if self.is(key, action) {
+            return Ok(());
+        }
+        if !KEYS.contains(&key) {
+            return Err(format!("{} cannot be bound", key_label(key)));
+        }
+        if let Some(other) = self.conflict(action, key) {
+            return Err(format!("{} is already used for {}", key_label(key), other.label()));
+        }
+        let keys = &mut self.bindings[action as usize];
+        if keys.len() >= MAX_BINDINGS {
+            return Err(format!("{} already has {} keys", action.label(), MAX_BINDINGS));
+        }
+        keys.push(key);
+        Ok(())
+    }
+
+    /// Removes the key added last to `action`. Every action keeps at least
+    /// one key, so none can get out of reach.
+    pub fn unbind_last(&mut self, action: Action) -> bool {
+        let keys = &mut self.bindings[action as usize];
+        keys.len() > 1 && keys.pop().is_some()
+    }
+
+    pub fn reset(&mut self) {
+        self.bindings = Action::ALL.iter().map(|action| action.defaults()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("snake-controls-{}-{}.txt", name, std::process::id()))
+    }
+
+    #[test]
+    fn defaults_do_not_conflict() {
+        let controls = Controls::new(Path::new("controls.txt"));
+        for action in Action::ALL {
+            for &key in controls.keys(action) {
+                assert_eq!(controls.conflict(action, key), None, "{} {:?}", action.name(), key);
+            }
+        }
+    }
+
+    #[test]
+    fn keys_conflict_only_within_a_scope() {
+        let controls = Controls::new(Path::new("controls.txt"));
+        // Escape pauses games and goes back in menus.
+        assert_eq!(controls.conflict(Action::Restart, KeyCode::Escape), Some(Action::Back));
+        assert_eq!(controls.conflict(Action::Autopilot, KeyCode::Escape), Some(Action::Pause));
+        assert_eq!(controls.conflict(Action::P2Up, KeyCode::Up), Some(Action::MoveUp));
+        assert_eq!(controls.conflict(Action::Restart, KeyCode::W), None);
+    }
+
+    #[test]
+    fn bind_refuses_conflicts_and_extra_keys() {
+        let mut controls = Controls::new(Path::new("controls.txt"));
+        assert!(controls.bind(Action::Pause, KeyCode::Return).is_ok());
+        assert_eq!(controls.action(KeyCode::Return, Scope::Game), Some(Action::Pause));
+        assert_eq!(controls.action(KeyCode::Return, Scope::Menu), Some(Action::Confirm));
+        assert!(controls.bind(Action::Pause, KeyCode::Return).is_ok());
+        assert_eq!(controls.keys(Action::Pause), [KeyCode::Escape, KeyCode::Return]);
+
+        assert!(controls.bind(Action::Restart, KeyCode::Return).is_err());
+        assert!(controls.bind(Action::MoveUp, KeyCode::MediaSelect).is_err());
+        assert!(controls.bind(Action::Pause, KeyCode::P).is_ok());
+        assert!(controls.bind(Action::Pause, KeyCode::Space).is_err());
+        assert_eq!(controls.keys(Action::Pause).len(), MAX_BINDINGS);
+    }
+
+    #[test]
+    fn unbind_keeps_one_key() {
+        let mut controls = Controls::new(Path::new("controls.txt"));
+        controls.bind(Action::MoveUp, KeyCode::I).unwrap();
+        assert!(controls.unbind_last(Action::MoveUp));
+        assert_eq!(controls.keys(Action::MoveUp), [KeyCode::Up]);
+        assert!(!controls.unbind_last(Action::MoveUp));
+        assert_eq!(controls.keys(Action::MoveUp), [KeyCode::Up]);
+    }
+
+    #[test]
+    fn saved_controls_load_back() {
+        let path = temp_path("round-trip");
+        let mut controls = Controls::new(&path);
+        controls.bind(Action::MoveUp, KeyCode::I).unwrap();
+        controls.bind(Action::Back, KeyCode::Back).unwrap();
+        controls.unbind_last(Action::Confirm);
+        controls.save().unwrap();
+        let loaded = Controls::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        for action in Action::ALL {
+            assert_eq!(loaded.keys(action), controls.keys(action), "{}", action.name());
+        }
+    }
+
+    #[test]
+    fn conflicting_file_falls_back_to_free_defaults() {
+        let path = temp_path("conflict");
+        fs::write(&path, format!("{}\nmove-up\tI\np2-up\tI\n", HEADER)).unwrap();
+        let loaded = Controls::load(&path);
+        assert_eq!(loaded.keys(Action::MoveUp), [KeyCode::I]);
+        assert_eq!(loaded.keys(Action::P2Up), [KeyCode::W]);
+
+        // Back loses Escape to Restart and its only default is Escape.
+        fs::write(&path, format!("{}\nrestart\tEscape\n", HEADER)).unwrap();
+        let loaded = Controls::load(&path);
+        fs::remove_file(&path).unwrap();
+        for action in Action::ALL {
+            assert_eq!(loaded.keys(action), action.defaults(), "{}", action.name());
+        }
+    }
+}
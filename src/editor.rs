@@ -0,0 +1,450 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use ggez::{
+    glam,
+    graphics::{self, Color, TextFragment},
+    input::keyboard::{KeyCode, KeyInput, KeyMods},
+    event::MouseButton,
+    Context, GameResult,
+};
+
+use crate::game::BoardView;
+use crate::level::{Level, WrapMode, MAX_GRID_SIDE, MAX_LEVEL_SPEED, MIN_GRID_SIDE, MIN_LEVEL_SPEED};
+use crate::sim::{Direction, GridPos};
+
+/// How many edits can be undone.
+const HISTORY_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Wall,
+    Spawn,
+    Food,
+}
+
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Wall => "Wall",
+            Tool::Spawn => "Spawn",
+            Tool::Food => "Food",
+        }
+    }
+}
+
+/// What the app should do after the editor handled a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorAction {
+    None,
+    Playtest,
+    Saved,
+    /// The board size changed, so the window should follow.
+    Resized,
+    Exit,
+}
+
+/// Paints a [`Level`] on the board with the mouse.
+///
+/// Left click uses the current tool, right click erases walls and food.
+/// Every stroke that changes something is one undo step.
+#[derive(Debug, Clone)]
+pub struct Editor {
+    pub level: Level,
+    pub view: BoardView,
+    tool: Tool,
+    /// `Some(true)` while painting, `Some(false)` while erasing.
+    stroke: Option<bool>,
+    renaming: bool,
+    /// The level before the stroke or rename in progress, kept as an undo
+    /// step once it is done if it changed anything.
+    pending: Option<Level>,
+    history: Vec<Level>,
+    future: Vec<Level>,
+    message: String,
+}
+
+impl Editor {
+    pub fn new(level: Level) -> Self {
+        Self {
+            view: BoardView::fit(level.size),
+            level,
+            tool: Tool::Wall,
+            stroke: None,
+            renaming: false,
+            pending: None,
+            history: Vec::new(),
+            future: Vec::new(),
+            message: String::new(),
+        }
+    }
+
+    /// Remembers the current level so the next change can be undone.
+    fn record(&mut self) {
+        self.settle();
+        self.push_history(self.level.clone());
+    }
+
+    /// Ends the stroke or rename in progress, keeping the level from before
+    /// it as an undo step unless nothing changed.
+    fn settle(&mut self) {
+        if let Some(before) = self.pending.take()
+            && before != self.level
+        {
+            self.push_history(before);
+        }
+    }
+
+    fn push_history(&mut self, level: Level) {
+        self.history.push(level);
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+        self.future.clear();
+    }
+
+    /// Returns whether the board size changed.
+    fn undo(&mut self) -> bool {
+        self.settle();
+        if let Some(prev) = self.history.pop() {
+            self.future.push(std::mem::replace(&mut self.level, prev));
+        }
+        self.refit()
+    }
+
+    /// Returns whether the board size changed.
+    fn redo(&mut self) -> bool {
+        self.settle();
+        if let Some(next) = self.future.pop() {
+            self.history.push(std::mem::replace(&mut self.level, next));
+        }
+        self.refit()
+    }
+
+    /// Fits the view to the level's board again, returning whether its size
+    /// changed.
+    fn refit(&mut self) -> bool {
+        if self.view.size == self.level.size {
+            return false;
+        }
+        self.view = BoardView::fit(self.level.size);
+        true
+    }
+
+    /// Grows or shrinks the board, dropping anything that no longer fits.
+    fn resize(&mut self, dw: i32, dh: i32) {
+        let (w, h) = self.level.size;
+        let size = (
+            (w + dw).clamp(MIN_GRID_SIDE, MAX_GRID_SIDE),
+            (h + dh).clamp(MIN_GRID_SIDE, MAX_GRID_SIDE),
+        );
+        if size == self.level.size {
+            return;
+        }
+        self.record();
+        let fits = |pos: &GridPos| pos.x < size.0 && pos.y < size.1;
+        let level = &mut self.level;
+        level.size = size;
+        level.obstacles.retain(fits);
+        level.food = level.food.filter(fits);
+        level.spawn = GridPos::new(level.spawn.x.min(size.0 - 1), level.spawn.y.min(size.1 - 1));
+        self.view = BoardView::fit(size);
+    }
+
+    fn set_speed(&mut self, speed: f32) {
+        let speed = speed.clamp(MIN_LEVEL_SPEED, MAX_LEVEL_SPEED);
+        if speed != self.level.speed {
+            self.record();
+            self.level.speed = speed;
+        }
+    }
+
+    fn apply(&mut self, pos: GridPos, paint: bool) {
+        let level = &mut self.level;
+        if !(0..level.size.0).contains(&pos.x) || !(0..level.size.1).contains(&pos.y) {
+            return;
+        }
+        if !paint {
+            level.obstacles.retain(|&p| p != pos);
+            if level.food == Some(pos) {
+                level.food = None;
+            }
+            return;
+        }
+        match self.tool {
+            Tool::Wall => {
+                if pos != level.spawn && !level.obstacles.contains(&pos) {
+                    level.obstacles.push(pos);
+                }
+            }
+            Tool::Spawn => level.spawn = pos,
+            Tool::Food => level.food = Some(pos),
+        }
+    }
+
+    pub fn mouse_button_down_event(&mut self, button: MouseButton, x: f32, y: f32) {
+        let paint = match button {
+            MouseButton::Left => true,
+            MouseButton::Right => false,
+            _ => return,
+        };
+        if let Some(pos) = self.view.cell_at(x, y) {
+            if self.pending.is_none() {
+                self.pending = Some(self.level.clone());
+            }
+            self.stroke = Some(paint);
+            self.apply(pos, paint);
+        }
+    }
+
+    pub fn mouse_motion_event(&mut self, x: f32, y: f32) {
+        if let (Some(paint), Some(pos)) = (self.stroke, self.view.cell_at(x, y)) {
+            self.apply(pos, paint);
+        }
+    }
+
+    pub fn mouse_button_up_event(&mut self) {
+        self.stroke = None;
+        // A stroke made while renaming ends with the rename.
+        if !self.renaming {
+            self.settle();
+        }
+    }
+
+    pub fn text_input_event(&mut self, character: char) {
+        if self.renaming && !character.is_control() {
+            self.level.name.push(character);
+        }
+    }
+
+    /// Writes the level over the file it was loaded from, or into `dir`
+    /// named after the level if it is new, unless it fails
+    /// [`Level::validate`].
+    pub fn save(&mut self, dir: &Path) -> Result<PathBuf, String> {
+        self.level.validate()?;
+        let path = match &self.level.path {
+            Some(path) => path.clone(),
+            None => {
+                let slug = self.level.slug();
+                if slug.is_empty() {
+                    return Err("the name needs a letter or digit to name the file after".to_string());
+                }
+                fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+                dir.join(format!("{}.lvl", slug))
+            }
+        };
+        fs::write(&path, self.level.to_file_string()).map_err(|err| err.to_string())?;
+        self.level.path = Some(path.clone());
+        Ok(path)
+    }
+
+    pub fn key_down_event(&mut self, input: KeyInput, levels_dir: &Path) -> EditorAction {
+        let Some(code) = input.keycode else {
+            return EditorAction::None;
+        };
+
+        if self.renaming {
+            match code {
+                KeyCode::Back => {
+                    self.level.name.pop();
+                }
+                KeyCode::Return | KeyCode::Escape => {
+                    if self.level.name.trim().is_empty() {
+                        self.level.name = "Untitled".to_string();
+                    }
+                    self.renaming = false;
+                    if self.stroke.is_none() {
+                        self.settle();
+                    }
+                }
+                _ => {}
+            }
+            return EditorAction::None;
+        }
+
+        if input.mods.contains(KeyMods::CTRL) {
+            let resized = match code {
+                KeyCode::Z => self.undo(),
+                KeyCode::Y => self.redo(),
+                KeyCode::S => match self.save(levels_dir) {
+                    Ok(path) => {
+                        self.message = format!("Saved {}", path.display());
+                        return EditorAction::Saved;
+                    }
+                    Err(err) => {
+                        self.message = format!("Cannot save: {}", err);
+                        false
+                    }
+                },
+                _ => false,
+            };
+            return if resized { EditorAction::Resized } else { EditorAction::None };
+        }
+
+        if input.mods.contains(KeyMods::SHIFT) {
+            let (dw, dh) = match code {
+                KeyCode::Left => (-1, 0),
+                KeyCode::Right => (1, 0),
+                KeyCode::Up => (0, -1),
+                KeyCode::Down => (0, 1),
+                _ => (0, 0),
+            };
+            if (dw, dh) != (0, 0) {
+                self.resize(dw, dh);
+                return EditorAction::Resized;
+            }
+        }
+
+        let turn = match code {
+            KeyCode::Up => Some(Direction::Up),
+            KeyCode::Down => Some(Direction::Down),
+            KeyCode::Left => Some(Direction::Left),
+            KeyCode::Right => Some(Direction::Right),
+            _ => None,
+        };
+        if let Some(dir) = turn {
+            if dir != self.level.direction {
+                self.record();
+                self.level.direction = dir;
+            }
+            return EditorAction::None;
+        }
+
+        match code {
+            KeyCode::Key1 => self.tool = Tool::Wall,
+            KeyCode::Key2 => self.tool = Tool::Spawn,
+            KeyCode::Key3 => self.tool = Tool::Food,
+            KeyCode::F2 => {
+                if self.pending.is_none() {
+                    self.pending = Some(self.level.clone());
+                }
+                self.renaming = true;
+            }
+            KeyCode::Equals | KeyCode::Plus | KeyCode::NumpadAdd => self.set_speed(self.level.speed + 1.0),
+            KeyCode::Minus | KeyCode::NumpadSubtract => self.set_speed(self.level.speed - 1.0),
+            KeyCode::W => {
+                self.record();
+                self.level.wrap = match self.level.wrap {
+                    WrapMode::Both => WrapMode::Off,
+                    WrapMode::Off => WrapMode::Horizontal,
+                    WrapMode::Horizontal => WrapMode::Vertical,
+                    WrapMode::Vertical => WrapMode::Both,
+                };
+            }
+            KeyCode::P => match self.level.validate() {
+                Ok(()) => return EditorAction::Playtest,
+                Err(err) => self.message = format!("Cannot playtest: {}", err),
+            },
+            KeyCode::Escape => return EditorAction::Exit,
+            _ => {}
+        }
+        EditorAction::None
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
+
+        let view = self.view;
+        let cell = |pos: GridPos, color: Color, canvas: &mut graphics::Canvas| {
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new().dest_rect(view.rect(pos)).color(color),
+            );
+        };
+
+        for pos in &self.level.obstacles {
+            cell(*pos, Color::from_rgb(120, 120, 130), &mut canvas);
+        }
+        if let Some(food) = self.level.food {
+            cell(food, Color::from_rgb(0, 0, 255), &mut canvas);
+        }
+        let level = &self.level;
+        if let Some(tail) = GridPos::new_from_move(level.spawn, level.direction.inverse(), level.size, WrapMode::Both) {
+            cell(tail, Color::from_rgb(92, 43, 117), &mut canvas);
+        }
+        cell(self.level.spawn, Color::from_rgb(236, 64, 122), &mut canvas);
+
+        let name = if self.renaming {
+            format!("{}_", self.level.name)
+        } else {
+            self.level.name.clone()
+        };
+        let lines = [
+            format!(
+                "{}   Size: {}x{}   Speed: {}   Wrap: {}   Tool: {}",
+                name,
+                self.level.size.0,
+                self.level.size.1,
+                self.level.speed,
+                self.level.wrap.name(),
+                self.tool.name()
+            ),
+            "[1] Wall [2] Spawn [3] Food  Arrows: direction  Shift+Arrows: size".to_string(),
+            "F2: rename  +/-: speed  W: wrap".to_string(),
+            "Ctrl+Z/Y: undo/redo  Ctrl+S: save  P: playtest  Esc: back".to_string(),
+        ];
+        let mut y = 8.0;
+        for line in lines {
+            let text = graphics::Text::new(TextFragment::new(line).scale(20.0));
+            canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(8.0, y)).color([1.0, 1.0, 1.0, 0.8]));
+            y += 22.0;
+        }
+        if !self.message.is_empty() {
+            let text = graphics::Text::new(TextFragment::new(self.message.clone()).scale(20.0));
+            let y = view.screen_size().1 - 30.0;
+            canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(8.0, y)).color([1.0, 1.0, 0.0, 1.0]));
+        }
+
+        canvas.finish(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clicks the middle of `pos` with `button`.
+    fn click(editor: &mut Editor, pos: GridPos, button: MouseButton) {
+        let rect = editor.view.rect(pos);
+        editor.mouse_button_down_event(button, rect.x + rect.w / 2.0, rect.y + rect.h / 2.0);
+        editor.mouse_button_up_event();
+    }
+
+    #[test]
+    fn strokes_that_change_nothing_are_not_undo_steps() {
+        let mut editor = Editor::new(Level::basic_levels().remove(0));
+        let wall = GridPos::new(1, 1);
+        click(&mut editor, wall, MouseButton::Left);
+        click(&mut editor, wall, MouseButton::Left);
+        click(&mut editor, GridPos::new(2, 2), MouseButton::Right);
+        assert_eq!(editor.history.len(), 1);
+
+        editor.undo();
+        assert!(!editor.level.obstacles.contains(&wall));
+        // Clicking the same wall again after an undo must not lose the redo.
+        click(&mut editor, GridPos::new(2, 2), MouseButton::Right);
+        assert_eq!(editor.future.len(), 1);
+    }
+
+    #[test]
+    fn renaming_is_one_undo_step_if_the_name_changed() {
+        let mut editor = Editor::new(Level::basic_levels().remove(0));
+        let name = editor.level.name.clone();
+        editor.pending = Some(editor.level.clone());
+        editor.renaming = true;
+        editor.text_input_event('!');
+        editor.level.name.pop();
+        editor.renaming = false;
+        editor.settle();
+        assert!(editor.history.is_empty());
+
+        editor.pending = Some(editor.level.clone());
+        editor.renaming = true;
+        editor.text_input_event('!');
+        editor.renaming = false;
+        editor.settle();
+        assert_eq!(editor.history.len(), 1);
+        editor.undo();
+        assert_eq!(editor.level.name, name);
+    }
+}
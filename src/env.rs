@@ -0,0 +1,523 @@
+use crate::controller::{ControllerKind, SnakeController};
+use crate::level::{Level, WrapMode};
+use crate::sim::{Ate, Direction, FoodKind, GridPos, Simulation, TickOutcome};
+
+/// How a move is given to [`SnakeEnv::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Keep going the way the snake is heading.
+    Straight,
+    /// Turn left or right of the current heading.
+    TurnLeft,
+    TurnRight,
+    /// Head in a direction on the board. Turning back is ignored, like on
+    /// the keyboard.
+    Go(Direction),
+}
+
+/// Which actions an agent picks from, for agents that output an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ActionSet {
+    /// `0` straight, `1` left, `2` right, relative to the heading.
+    #[default]
+    Relative,
+    /// `0` up, `1` down, `2` left, `3` right, in the order of
+    /// [`Direction::ALL`].
+    Absolute,
+}
+
+impl ActionSet {
+    /// How many actions there are to pick from.
+    pub fn count(self) -> usize {
+        match self {
+            ActionSet::Relative => 3,
+            ActionSet::Absolute => Direction::ALL.len(),
+        }
+    }
+
+    pub fn action(self, index: usize) -> Option<Action> {
+        match self {
+            ActionSet::Relative => [Action::Straight, Action::TurnLeft, Action::TurnRight].get(index).copied(),
+            ActionSet::Absolute => Direction::ALL.get(index).map(|&dir| Action::Go(dir)),
+        }
+    }
+}
+
+fn turn_left(dir: Direction) -> Direction {
+    match dir {
+        Direction::Up => Direction::Left,
+        Direction::Left => Direction::Down,
+        Direction::Down => Direction::Right,
+        Direction::Right => Direction::Up,
+    }
+}
+
+fn turn_right(dir: Direction) -> Direction {
+    turn_left(dir).inverse()
+}
+
+/// How the board is handed to the agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// One `height x width` plane per channel, in the order of
+    /// [`GRID_CHANNELS`], with `1.0` where the channel applies.
+    #[default]
+    Grid,
+    /// The [`FEATURES`] below, for small networks and tabular agents.
+    Features,
+}
+
+/// Planes of the [`Encoding::Grid`] observation.
+pub const GRID_CHANNELS: [&str; 5] = ["head", "body", "food", "walls", "rivals"];
+
+/// Entries of the [`Encoding::Features`] observation:
+///
+/// - whether moving straight, left or right kills the snake at once,
+/// - the heading, one-hot in the order of [`Direction::ALL`],
+/// - whether the food is up, down, left or right of the head, taking the
+///   shorter way round wrapping edges,
+/// - the food kind, one-hot in the order of [`FoodKind::ALL`],
+/// - the snake's length as a share of the board.
+pub const FEATURES: usize = 3 + 4 + 4 + FoodKind::ALL.len() + 1;
+
+/// What the agent sees: a flat tensor and its shape, `[channels, height,
+/// width]` for grids and `[features]` for feature vectors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub shape: Vec<usize>,
+    pub data: Vec<f32>,
+}
+
+/// Rewards given on each step. The defaults score the food eaten and
+/// punish dying; the rest is off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rewards {
+    /// Per point scored, so bonus food is worth more.
+    pub food: f32,
+    pub death: f32,
+    /// For filling the board, or winning against the opponents.
+    pub win: f32,
+    /// Added on every step, usually a small negative value to hurry the
+    /// agent up.
+    pub step: f32,
+    /// Given when the head gets one step closer to the food and taken away
+    /// when it gets farther.
+    pub approach: f32,
+}
+
+impl Default for Rewards {
+    fn default() -> Self {
+        Rewards { food: 1.0, death: -1.0, win: 10.0, step: 0.0, approach: 0.0 }
+    }
+}
+
+/// Everything that stays the same between episodes.
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    pub level: Level,
+    pub encoding: Encoding,
+    pub actions: ActionSet,
+    pub rewards: Rewards,
+    /// AIs playing the other snakes, if any; the agent is always player 0.
+    pub opponents: Vec<ControllerKind>,
+    /// Steps after which an episode is cut short, or 0 for no limit.
+    pub max_steps: u32,
+}
+
+impl EnvConfig {
+    pub fn new(level: Level) -> Self {
+        EnvConfig {
+            level,
+            encoding: Encoding::default(),
+            actions: ActionSet::default(),
+            rewards: Rewards::default(),
+            opponents: Vec::new(),
+            max_steps: 0,
+        }
+    }
+}
+
+/// Extra facts about a step that are not part of the reward.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Info {
+    pub score: u32,
+    pub length: usize,
+    pub ticks: u32,
+    /// Food eaten on this step.
+    pub ate: Option<FoodKind>,
+    /// What killed the agent's snake, once it is dead.
+    pub death: Option<Ate>,
+    /// Whether the board was filled or the agent won against the
+    /// opponents.
+    pub won: bool,
+    /// Whether the episode ended at [`EnvConfig::max_steps`] rather than by
+    /// the rules.
+    pub truncated: bool,
+}
+
+/// The game as a reinforcement learning environment, in the style of Gym:
+/// [`reset`](SnakeEnv::reset) starts an episode and
+/// [`step`](SnakeEnv::step) plays one tick of it.
+///
+/// Steps run [`Simulation::tick`], so agents learn exactly the rules the
+/// game is played by.
+pub struct SnakeEnv {
+    config: EnvConfig,
+    sim: Simulation,
+    opponents: Vec<Box<dyn SnakeController>>,
+    steps: u32,
+}
+
+impl SnakeEnv {
+    pub fn new(config: EnvConfig) -> Self {
+        SnakeEnv {
+            sim: Simulation::with_players(config.level.clone(), 0, 1 + config.opponents.len()),
+            opponents: config.opponents.iter().map(|kind| kind.build()).collect(),
+            steps: 0,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &EnvConfig {
+        &self.config
+    }
+
+    /// The game being played, for rendering or debugging.
+    pub fn sim(&self) -> &Simulation {
+        &self.sim
+    }
+
+    /// Shape of every observation this environment returns.
+    pub fn observation_shape(&self) -> Vec<usize> {
+        let (w, h) = self.config.level.size;
+        match self.config.encoding {
+            Encoding::Grid => vec![GRID_CHANNELS.len(), h as usize, w as usize],
+            Encoding::Features => vec![FEATURES],
+        }
+    }
+
+    /// Starts a new episode whose food placement is fully determined by
+    /// `seed`.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        let players = 1 + self.config.opponents.len();
+        self.sim = Simulation::with_players(self.config.level.clone(), seed, players);
+        self.opponents = self.config.opponents.iter().map(|kind| kind.build()).collect();
+        self.steps = 0;
+        self.observe()
+    }
+
+    fn is_done(&self) -> bool {
+        self.sim.is_over()
+            || !self.sim.players()[0].is_alive()
+            || (self.config.max_steps > 0 && self.steps >= self.config.max_steps)
+    }
+
+    fn won(&self) -> bool {
+        if self.sim.players().len() > 1 { self.sim.winner() == Some(0) } else { self.sim.is_won() }
+    }
+
+    /// Plays one tick with the agent's `action` and the opponents' moves.
+    /// Returns the new observation, the reward, whether the episode is over
+    /// and some [`Info`]. Once it is over, further steps change nothing
+    /// until [`reset`](SnakeEnv::reset).
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool, Info) {
+        if self.is_done() {
+            return (self.observe(), 0.0, true, self.info(None));
+        }
+
+        let heading = self.sim.snake().dir;
+        let dir = match action {
+            Action::Straight => heading,
+            Action::TurnLeft => turn_left(heading),
+            Action::TurnRight => turn_right(heading),
+            Action::Go(dir) => dir,
+        };
+        if dir != heading {
+            self.sim.steer_player(0, dir);
+        }
+        for (i, opponent) in self.opponents.iter_mut().enumerate() {
+            let player = i + 1;
+            if self.sim.players()[player].is_alive()
+                && let Some(dir) = opponent.decide(&self.sim, player)
+            {
+                self.sim.steer_player(player, dir);
+            }
+        }
+
+        let before = self.food_distance();
+        let score = self.sim.score();
+        let food = self.sim.food().map(|food| food.kind);
+        let outcome = self.sim.tick()[0];
+        self.steps += 1;
+
+        let rewards = self.config.rewards;
+        let mut reward = rewards.step + rewards.food * (self.sim.score() - score) as f32;
+        let ate = match outcome {
+            TickOutcome::Ate(Ate::Food(kind)) => Some(kind),
+            // The last food was eaten and none could take its place.
+            TickOutcome::BoardFull => food,
+            _ => None,
+        };
+        if self.sim.players()[0].is_alive() {
+            if ate.is_none()
+                && let (Some(before), Some(after)) = (before, self.food_distance())
+            {
+                reward += rewards.approach * (before - after).signum() as f32;
+            }
+        } else {
+            reward += rewards.death;
+        }
+        if self.sim.is_over() && self.won() {
+            reward += rewards.win;
+        }
+
+        let done = self.is_done();
+        (self.observe(), reward, done, self.info(ate))
+    }
+
+    fn info(&self, ate: Option<FoodKind>) -> Info {
+        let player = &self.sim.players()[0];
+        Info {
+            score: player.score(),
+            length: player.snake().len(),
+            ticks: self.sim.ticks(),
+            ate,
+            death: player.death(),
+            won: self.sim.is_over() && self.won(),
+            truncated: !self.sim.is_over() && player.is_alive() && self.is_done(),
+        }
+    }
+
+    /// Steps from the head to the food on each axis, the shorter way round
+    /// wrapping edges, signed like the board's coordinates.
+    fn food_offset(&self) -> Option<(i32, i32)> {
+        let food = self.sim.food()?.pos;
+        let head = self.sim.snake().head.pos;
+        let level = self.sim.level();
+        let (w, h) = level.size;
+        let shortest = |delta: i32, side: i32, wraps: bool| {
+            if wraps && delta.abs() * 2 > side { delta - side * delta.signum() } else { delta }
+        };
+        let wrap_x = matches!(level.wrap, WrapMode::Both | WrapMode::Horizontal);
+        let wrap_y = matches!(level.wrap, WrapMode::Both | WrapMode::Vertical);
+        Some((shortest(food.x - head.x, w, wrap_x), shortest(food.y - head.y, h, wrap_y)))
+    }
+
+    fn food_distance(&self) -> Option<i32> {
+        self.food_offset().map(|(dx, dy)| dx.abs() + dy.abs())
+    }
+
+    /// Whether the agent's snake would die moving into `pos` on the next
+    /// tick. Other snakes' tail tips move out of the way; its own does not.
+    fn is_deadly(&self, pos: Option<GridPos>) -> bool {
+        let Some(pos) = pos else {
+            return true;
+        };
+        if self.sim.level().obstacles.contains(&pos) {
+            return true;
+        }
+        self.sim.players().iter().enumerate().filter(|(_, p)| p.is_alive()).any(|(i, p)| {
+            let snake = p.snake();
+            let cells = if i == 0 { snake.len() } else { snake.len() - 1 };
+            snake.cells().take(cells).any(|cell| cell == pos)
+        })
+    }
+
+    /// The current state in the configured [`Encoding`].
+    pub fn observe(&self) -> Observation {
+        match self.config.encoding {
+            Encoding::Grid => self.observe_grid(),
+            Encoding::Features => self.observe_features(),
+        }
+    }
+
+    fn observe_grid(&self) -> Observation {
+        let shape = self.observation_shape();
+        let (w, h) = self.config.level.size;
+        let plane = (w * h) as usize;
+        let mut data = vec![0.0; shape.iter().product()];
+        let mut set = |channel: usize, pos: GridPos| data[channel * plane + (pos.y * w + pos.x) as usize] = 1.0;
+
+        for (i, player) in self.sim.players().iter().enumerate().filter(|(_, p)| p.is_alive()) {
+            let snake = player.snake();
+            if i == 0 {
+                set(0, snake.head.pos);
+                for seg in &snake.body {
+                    set(1, seg.pos);
+                }
+            } else {
+                for pos in snake.cells() {
+                    set(4, pos);
+                }
+            }
+        }
+        if let Some(food) = self.sim.food() {
+            set(2, food.pos);
+        }
+        for &pos in &self.sim.level().obstacles {
+            set(3, pos);
+        }
+        Observation { shape, data }
+    }
+
+    fn observe_features(&self) -> Observation {
+        let snake = self.sim.snake();
+        let level = self.sim.level();
+        let head = snake.head.pos;
+        let step = |dir| GridPos::new_from_move(head, dir, level.size, level.wrap);
+        let flag = |b: bool| if b { 1.0 } else { 0.0 };
+
+        let mut data = Vec::with_capacity(FEATURES);
+        for dir in [snake.dir, turn_left(snake.dir), turn_right(snake.dir)] {
+            data.push(flag(self.is_deadly(step(dir))));
+        }
+        data.extend(Direction::ALL.map(|dir| flag(dir == snake.dir)));
+        let (dx, dy) = self.food_offset().unwrap_or((0, 0));
+        data.extend([flag(dy < 0), flag(dy > 0), flag(dx < 0), flag(dx > 0)]);
+        let kind = self.sim.food().map(|food| food.kind);
+        data.extend(FoodKind::ALL.map(|k| flag(kind == Some(k))));
+        let (w, h) = level.size;
+        data.push(snake.len() as f32 / (w * h) as f32);
+
+        Observation { shape: vec![FEATURES], data }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn easy() -> Level {
+        Level::basic_levels().remove(0)
+    }
+
+    fn env(level: Level, encoding: Encoding) -> SnakeEnv {
+        let mut config = EnvConfig::new(level);
+        config.encoding = encoding;
+        SnakeEnv::new(config)
+    }
+
+    /// The cells of grid observation `obs` set in `channel`.
+    fn plane(obs: &Observation, channel: usize, w: i32) -> Vec<GridPos> {
+        let plane = obs.shape[1] * obs.shape[2];
+        obs.data[channel * plane..(channel + 1) * plane]
+            .iter()
+            .enumerate()
+            .filter(|&(_, &v)| v == 1.0)
+            .map(|(i, _)| GridPos::new(i as i32 % w, i as i32 / w))
+            .collect()
+    }
+
+    #[test]
+    fn grid_observation() {
+        let mut level = easy();
+        level.obstacles = vec![GridPos::new(1, 2), GridPos::new(3, 4)];
+        let mut env = env(level, Encoding::Grid);
+        let obs = env.reset(3);
+        assert_eq!(obs.shape, [GRID_CHANNELS.len(), 20, 30]);
+        assert_eq!(obs.shape, env.observation_shape());
+        assert_eq!(obs.data.len(), 5 * 20 * 30);
+        assert_eq!(plane(&obs, 0, 30), [GridPos::new(7, 10)]);
+        assert_eq!(plane(&obs, 1, 30), [GridPos::new(6, 10)]);
+        assert_eq!(plane(&obs, 2, 30), [env.sim().food().unwrap().pos]);
+        assert_eq!(plane(&obs, 3, 30), [GridPos::new(1, 2), GridPos::new(3, 4)]);
+        assert!(plane(&obs, 4, 30).is_empty());
+    }
+
+    #[test]
+    fn feature_observation() {
+        let mut level = easy();
+        level.food = Some(GridPos::new(7, 5));
+        level.obstacles = vec![GridPos::new(8, 10)];
+        let mut env = env(level, Encoding::Features);
+        let obs = env.reset(3);
+        assert_eq!(obs.shape, [FEATURES]);
+        assert_eq!(obs.data.len(), FEATURES);
+        // Straight ahead is a wall; the snake heads right; the food is up.
+        assert_eq!(obs.data[..11], [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(obs.data[11..16], [1.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(obs.data[16], 2.0 / 600.0);
+    }
+
+    #[test]
+    fn rewards_for_food_and_death() {
+        let mut level = easy();
+        level.wrap = WrapMode::Off;
+        level.spawn = GridPos::new(27, 10);
+        level.food = Some(GridPos::new(28, 10));
+        let mut env = env(level, Encoding::Features);
+        env.reset(0);
+
+        let (_, reward, done, info) = env.step(Action::Straight);
+        assert_eq!((reward, done), (1.0, false));
+        assert_eq!(info.ate, Some(FoodKind::Normal));
+        assert_eq!((info.score, info.length), (1, 3));
+
+        env.step(Action::Go(Direction::Right));
+        let (_, reward, done, info) = env.step(Action::Straight);
+        assert_eq!((reward, done), (-1.0, true));
+        assert_eq!(info.death, Some(Ate::Wall));
+        assert!(!info.won && !info.truncated);
+
+        // Nothing changes after the end.
+        let (_, reward, done, after) = env.step(Action::TurnLeft);
+        assert_eq!((reward, done), (0.0, true));
+        assert_eq!(after, Info { ate: None, ..info });
+    }
+
+    #[test]
+    fn reward_for_filling_the_board() {
+        // A 5x5 board that is all wall but the snake and one food.
+        let mut level = easy();
+        level.size = (5, 5);
+        level.wrap = WrapMode::Off;
+        level.spawn = GridPos::new(1, 0);
+        level.food = Some(GridPos::new(2, 0));
+        level.obstacles = (0..5)
+            .flat_map(|y| (0..5).map(move |x| GridPos::new(x, y)))
+            .filter(|pos| pos.y > 0 || pos.x > 2)
+            .collect();
+        let mut env = env(level, Encoding::Grid);
+        env.reset(0);
+        let (_, reward, done, info) = env.step(Action::Straight);
+        assert_eq!((reward, done), (1.0 + 10.0, true));
+        assert_eq!(info.ate, Some(FoodKind::Normal));
+        assert!(info.won);
+        assert_eq!(info.death, None);
+    }
+
+    #[test]
+    fn episodes_are_cut_at_max_steps() {
+        let mut config = EnvConfig::new(easy());
+        config.max_steps = 3;
+        config.rewards.step = -0.5;
+        let mut env = SnakeEnv::new(config);
+        env.reset(0);
+        for _ in 0..2 {
+            assert!(!env.step(Action::Straight).2);
+        }
+        let (_, reward, done, info) = env.step(Action::Straight);
+        assert_eq!((reward, done), (-0.5, true));
+        assert!(info.truncated);
+    }
+
+    #[test]
+    fn same_seed_same_episode() {
+        let mut config = EnvConfig::new(easy());
+        config.opponents = vec![ControllerKind::Bfs];
+        let run = |seed: u64| {
+            let mut env = SnakeEnv::new(config.clone());
+            let mut steps = vec![(env.reset(seed), 0.0, false)];
+            for i in 0..300 {
+                let (obs, reward, done, _) = env.step(ActionSet::Relative.action(i % 7 / 3).unwrap());
+                steps.push((obs, reward, done));
+                if done {
+                    break;
+                }
+            }
+            (steps, env.sim().checksum())
+        };
+        assert_eq!(run(5), run(5));
+        assert_ne!(run(5).1, run(6).1);
+    }
+}
@@ -1,327 +1,963 @@
-use oorandom::Rand32;
 use rand::{self, TryRngCore};
 
 use ggez::{
-    audio::Source, graphics::{self, Color}, input::keyboard::{KeyCode, KeyInput}, Context, GameResult
+    graphics::{self, Color}, input::keyboard::{KeyCode, KeyInput, KeyMods}, Context, GameResult
 };
 
-use std::collections::VecDeque;
-
-use crate::AppScene;
 use crate::audio::AudioManager;
+use crate::controls::{Action, Controls, Scope};
+use crate::controller::{self, ControllerKind, SnakeController};
+use crate::level::{Level, WrapMode};
+use crate::menu;
+use crate::net::{self, Broadcast, Feed, Session, Spectator, Status};
+use crate::replay::{Input, Replay, ReplayPlayer};
+use crate::sim::{Ate, Direction, Food, FoodKind, GridPos, Simulation, Snake, TickOutcome};
+
+/// The window size used for menus, and the largest area a board is scaled
+/// to fit into.
+pub const SCREEN_SIZE: (f32, f32) = (960.0, 640.0);
+
+/// Cells never get bigger than this, so tiny boards stay playable.
+const MAX_CELL_SIZE: f32 = 48.0;
+
+/// Maps grid cells to pixels for a board of a given size.
+#[derive(Debug, Clone, Copy)]
+pub struct BoardView {
+    pub size: (i32, i32),
+    pub cell: f32,
+}
 
-pub const GRID_SIZE: (i32, i32) = (30, 20);
+impl BoardView {
+
+    /// Picks the largest whole-pixel cell size that fits the board in
+    /// [`SCREEN_SIZE`].
+    pub fn fit(size: (i32, i32)) -> Self {
+        let cell = (SCREEN_SIZE.0 / size.0 as f32)
+            .min(SCREEN_SIZE.1 / size.1 as f32)
+            .floor()
+            .clamp(1.0, MAX_CELL_SIZE);
+        BoardView { size, cell }
+    }
 
-pub const GRID_CELL_SIZE: (i32, i32) = (32, 32);
+    pub fn screen_size(&self) -> (f32, f32) {
+        (self.size.0 as f32 * self.cell, self.size.1 as f32 * self.cell)
+    }
 
-pub const SCREEN_SIZE: (f32, f32) = (
-    GRID_SIZE.0 as f32 * GRID_CELL_SIZE.0 as f32,
-    GRID_SIZE.1 as f32 * GRID_CELL_SIZE.1 as f32,
-);
+    pub fn rect(&self, pos: GridPos) -> graphics::Rect {
+        graphics::Rect::new(
+            pos.x as f32 * self.cell,
+            pos.y as f32 * self.cell,
+            self.cell,
+            self.cell,
+        )
+    }
 
-pub const DESIRED_FPS: u32 = 8;
+    /// The cell under a point on screen, if it is on the board.
+    pub fn cell_at(&self, x: f32, y: f32) -> Option<GridPos> {
+        let pos = GridPos::new((x / self.cell).floor() as i32, (y / self.cell).floor() as i32);
+        (pos.x >= 0 && pos.y >= 0 && pos.x < self.size.0 && pos.y < self.size.1).then_some(pos)
+    }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub struct GridPos {
-    pub x: i32,
-    pub y: i32,
+    /// Resizes the window to exactly fit the board.
+    pub fn resize_window(&self, ctx: &mut Context) -> GameResult {
+        let (w, h) = self.screen_size();
+        ctx.gfx.set_drawable_size(w, h)
+    }
 }
 
-impl GridPos {
- 
-    pub fn new(x: i32, y: i32) -> Self {
-        GridPos { x, y }
+fn draw_obstacles(obstacles: &[GridPos], view: &BoardView, canvas: &mut graphics::Canvas) {
+
+    for pos in obstacles {
+
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(view.rect(*pos))
+                .color(Color::from_rgb(120, 120, 130)),
+        );
     }
+}
 
-    pub fn random(rng: &mut Rand32, max_x: i32, max_y: i32) -> Self {
-        (
-            rng.rand_range(0..(max_x as u32)) as i32,
-            rng.rand_range(0..(max_y as u32)) as i32,
-        )
-            .into()
-    }
-    
-    fn new_from_move(pos: GridPos, dir: Direction) -> Self {
-        match dir {
-            Direction::Up => GridPos::new(pos.x, (pos.y - 1).rem_euclid(GRID_SIZE.1)),
-            Direction::Down => GridPos::new(pos.x, (pos.y + 1).rem_euclid(GRID_SIZE.1)),
-            Direction::Left => GridPos::new((pos.x - 1).rem_euclid(GRID_SIZE.0), pos.y),
-            Direction::Right => GridPos::new((pos.x + 1).rem_euclid(GRID_SIZE.0), pos.y),
-        }
+/// Thickness in pixels of the walls drawn along edges that do not wrap.
+const BORDER_WIDTH: f32 = 4.0;
+
+fn draw_border(wrap: WrapMode, view: &BoardView, canvas: &mut graphics::Canvas) {
+
+    let (w, h) = view.screen_size();
+    let mut edges = Vec::new();
+    if !matches!(wrap, WrapMode::Both | WrapMode::Horizontal) {
+        edges.push(graphics::Rect::new(0.0, 0.0, BORDER_WIDTH, h));
+        edges.push(graphics::Rect::new(w - BORDER_WIDTH, 0.0, BORDER_WIDTH, h));
+    }
+    if !matches!(wrap, WrapMode::Both | WrapMode::Vertical) {
+        edges.push(graphics::Rect::new(0.0, 0.0, w, BORDER_WIDTH));
+        edges.push(graphics::Rect::new(0.0, h - BORDER_WIDTH, w, BORDER_WIDTH));
+    }
+    for edge in edges {
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(edge)
+                .color(Color::from_rgb(200, 200, 210)),
+        );
     }
 }
 
-impl From<GridPos> for graphics::Rect {
-    fn from(pos: GridPos) -> Self {
-        graphics::Rect::new_i32(
-            pos.x as i32 * GRID_CELL_SIZE.0 as i32,
-            pos.y as i32 * GRID_CELL_SIZE.1 as i32,
-            GRID_CELL_SIZE.0 as i32,
-            GRID_CELL_SIZE.1 as i32,
-        )
+fn food_color(kind: FoodKind) -> Color {
+    match kind {
+        FoodKind::Normal => Color::from_rgb(0, 0, 255),
+        FoodKind::Bonus => Color::from_rgb(255, 200, 0),
+        FoodKind::Shrink => Color::from_rgb(230, 40, 40),
+        FoodKind::SpeedUp => Color::from_rgb(0, 220, 220),
+        FoodKind::SlowDown => Color::from_rgb(60, 200, 60),
     }
 }
 
-impl From<(i32, i32)> for GridPos {
-    fn from(pos: (i32, i32)) -> Self {
-        GridPos { x: pos.0, y: pos.1 }
+/// Sound played when `kind` is eaten, see [`AudioManager::load_sfx`].
+fn food_sfx(kind: FoodKind) -> &'static str {
+    match kind {
+        FoodKind::Normal => "eat",
+        FoodKind::Bonus => "bonus",
+        FoodKind::Shrink => "shrink",
+        FoodKind::SpeedUp => "speedup",
+        FoodKind::SlowDown => "slowdown",
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
+fn draw_food(food: &Food, view: &BoardView, canvas: &mut graphics::Canvas) {
+
+    let mut color = food_color(food.kind);
+
+    // Special food blinks during its last few ticks.
+    if food.ticks_left.is_some_and(|ticks| ticks <= 10 && ticks % 2 == 0) {
+        color.a = 0.4;
+    }
+
+    canvas.draw(
+        &graphics::Quad,
+        graphics::DrawParam::new()
+            .dest_rect(view.rect(food.pos))
+            .color(color),
+    );
 }
 
-impl Direction {
-  
-    pub fn inverse(self) -> Self {
-        match self {
-            Direction::Up => Direction::Down,
-            Direction::Down => Direction::Up,
-            Direction::Left => Direction::Right,
-            Direction::Right => Direction::Left,
-        }
+/// Head and body colors of each player's snake.
+const SNAKE_COLORS: [[(u8, u8, u8); 2]; 4] = [
+    [(236, 64, 122), (92, 43, 117)],
+    [(38, 198, 218), (0, 96, 100)],
+    [(255, 193, 7), (130, 90, 0)],
+    [(102, 187, 106), (27, 94, 32)],
+];
+
+/// Draws the snake of `player`, faded out if it is `dead`.
+fn draw_snake(snake: &Snake, player: usize, dead: bool, view: &BoardView, canvas: &mut graphics::Canvas) {
+
+    let [head, body] = SNAKE_COLORS[player % SNAKE_COLORS.len()];
+    let alpha = if dead { 90 } else { 255 };
+
+    for seg in &snake.body {
+
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(view.rect(seg.pos))
+                .color(Color::from_rgba(body.0, body.1, body.2, alpha)),
+        );
     }
 
-    pub fn from_keycode(key: KeyCode) -> Option<Direction> {
-        match key {
-            KeyCode::Up => Some(Direction::Up),
-            KeyCode::Down => Some(Direction::Down),
-            KeyCode::Left => Some(Direction::Left),
-            KeyCode::Right => Some(Direction::Right),
-            _ => None,
+    canvas.draw(
+        &graphics::Quad,
+        graphics::DrawParam::new()
+            .dest_rect(view.rect(snake.head.pos))
+            .color(Color::from_rgba(head.0, head.1, head.2, alpha)),
+    );
+}
+
+/// Height in pixels of the status strip drawn over the top of the board.
+const HUD_HEIGHT: f32 = 28.0;
+
+/// Formats seconds as `m:ss`.
+pub fn format_time(secs: f32) -> String {
+    let secs = secs as u32;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// `controllers` names what drives each snake, if known; the keyboard is
+/// not mentioned.
+fn draw_hud(sim: &Simulation, controllers: &[ControllerKind], view: &BoardView, canvas: &mut graphics::Canvas) {
+
+    let strip = graphics::Rect::new(0.0, 0.0, view.screen_size().0, HUD_HEIGHT);
+    canvas.draw(
+        &graphics::Quad,
+        graphics::DrawParam::new()
+            .dest_rect(strip)
+            .color(Color::new(0.0, 0.0, 0.0, 0.5)),
+    );
+
+    let ai = |i: usize| controllers.get(i).copied().filter(|&kind| kind != ControllerKind::Keyboard);
+    let status = if sim.players().len() > 1 {
+        let scores: Vec<String> = sim
+            .players()
+            .iter()
+            .enumerate()
+            .map(|(i, p)| match ai(i) {
+                Some(kind) => format!("P{} ({}): {}", i + 1, kind.name(), p.score()),
+                None => format!("P{}: {}", i + 1, p.score()),
+            })
+            .collect();
+        scores.join("   ")
+    } else {
+        let mut status = format!("Score: {}   Length: {}", sim.score(), sim.snake().len());
+        if let Some(kind) = ai(0) {
+            status.push_str(&format!("   Autopilot: {}", kind.name()));
         }
+        status
+    };
+    let text = graphics::Text::new(
+        graphics::TextFragment::new(format!(
+            "{}   {}   Time: {}",
+            sim.level().name,
+            status,
+            format_time(sim.elapsed_secs()),
+        ))
+        .scale(20.0),
+    );
+    canvas.draw(
+        &text,
+        graphics::DrawParam::new()
+            .dest(ggez::glam::Vec2::new(8.0, 4.0))
+            .color(Color::WHITE),
+    );
+}
+
+/// Draws the board, snake, food and HUD of `sim` without finishing `canvas`.
+fn draw_sim(sim: &Simulation, controllers: &[ControllerKind], view: &BoardView, canvas: &mut graphics::Canvas) {
+    draw_obstacles(&sim.level().obstacles, view, canvas);
+    draw_border(sim.level().wrap, view, canvas);
+    // Dead snakes fade out while the others play on.
+    for (i, player) in sim.players().iter().enumerate() {
+        draw_snake(player.snake(), i, !player.is_alive() && !sim.is_over(), view, canvas);
     }
+    if let Some(food) = sim.food() {
+        draw_food(food, view, canvas);
+    }
+    draw_hud(sim, controllers, view, canvas);
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Segment {
-    pos: GridPos,
+/// Kinds of games; each one that keeps scores has its own high score
+/// tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    Classic,
+    /// Two players on one keyboard, by default arrows against WASD.
+    Versus,
 }
 
-impl Segment {
-    pub fn new(pos: GridPos) -> Self {
-        Segment { pos }
+impl GameMode {
+    pub const ALL: [GameMode; 2] = [GameMode::Classic, GameMode::Versus];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Classic => "classic",
+            GameMode::Versus => "versus",
+        }
+    }
+
+    pub fn players(self) -> usize {
+        match self {
+            GameMode::Classic => 1,
+            GameMode::Versus => 2,
+        }
+    }
+
+    /// Versus games are won against the other player, not a score table.
+    pub fn keeps_scores(self) -> bool {
+        self == GameMode::Classic
     }
 }
 
-#[derive(Debug, Clone)]
-struct Obsta {
-    data: Vec<GridPos>,
+/// Plays the sound effects for what happened in one tick.
+fn play_sounds(outcomes: &[TickOutcome], audio: &mut AudioManager, ctx: &mut Context) {
+    for &outcome in outcomes {
+        match outcome {
+            TickOutcome::Ate(Ate::Food(kind)) => {
+                let name = food_sfx(kind);
+                audio.play_sfx(if audio.has_sfx(name) { name } else { "eat" }, ctx);
+            }
+            TickOutcome::BoardFull => audio.play_sfx("eat", ctx),
+            TickOutcome::Ate(Ate::Itself | Ate::Obstacle | Ate::Wall | Ate::Rival | Ate::HeadOn) => {
+                audio.play_sfx("die", ctx)
+            }
+            TickOutcome::Moved | TickOutcome::GameOver => {}
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-struct Obstacles {
-    data: Vec<Obsta>,
+fn random_seed() -> u64 {
+    let mut seed = [0u8; 8];
+    let _ = rand::rngs::OsRng.try_fill_bytes(&mut seed);
+    u64::from_ne_bytes(seed)
 }
 
-#[derive(Debug, Clone)]
-struct Food {
-    pos: GridPos,
+/// Plugs a [`Simulation`] into ggez: ticks it on a timer, asks each
+/// snake's controller where to go, draws it and plays sounds for what
+/// happened.
+pub struct GameState {
+    sim: Simulation,
+    view: BoardView,
+    mode: GameMode,
+    controllers: Vec<Box<dyn SnakeController>>,
+    kinds: Vec<ControllerKind>,
+    /// Set once an AI has driven the first snake, so the run does not count
+    /// for the high scores.
+    assisted: bool,
+    /// Every turn made so far, for the replay.
+    inputs: Vec<Input>,
 }
 
-impl Food {
-    pub fn new(pos: GridPos) -> Self {
-        Food { pos }
+impl GameState {
+
+    pub fn new(level: Level, mode: GameMode) -> Self {
+        GameState::with_seed(level, mode, random_seed())
     }
-    
-    fn draw(&self, canvas: &mut graphics::Canvas) {
-     
-        let color = [0.0, 0.0, 1.0, 1.0];
-    
-        canvas.draw(
-            &graphics::Quad,
-            graphics::DrawParam::new()
-                .dest_rect(self.pos.into())
-                .color(color),
-        );
+
+    /// Starts a game whose food placement is fully determined by `seed`.
+    pub fn with_seed(level: Level, mode: GameMode, seed: u64) -> Self {
+        let kinds = vec![ControllerKind::Keyboard; mode.players()];
+        GameState {
+            view: BoardView::fit(level.size),
+            sim: Simulation::with_players(level, seed, mode.players()),
+            mode,
+            controllers: kinds.iter().map(|kind| kind.build()).collect(),
+            kinds,
+            assisted: false,
+            inputs: Vec::new(),
+        }
     }
-}
 
-#[derive(Clone, Copy, Debug)]
-enum Ate {
-    Itself,
-    Food,
-}
+    /// Lets each snake keep up to `depth` turns for the coming ticks
+    /// instead of [`TURN_BUFFER`](crate::sim::TURN_BUFFER).
+    pub fn with_turn_buffer(mut self, depth: usize) -> Self {
+        self.sim = self.sim.with_turn_buffer(depth);
+        self
+    }
 
-#[derive(Debug, Clone)]
-struct Snake {
-    head: Segment,
-    dir: Direction,
-    body: VecDeque<Segment>,
-    ate: Option<Ate>,
-    last_update_dir: Direction,
-    next_dir: Option<Direction>,
-}
+    /// A game with `players` snakes that nobody here steers; its ticks come
+    /// in through [`GameState::follow`].
+    pub fn spectating(level: Level, seed: u64, players: usize) -> Self {
+        let mode = if players > 1 { GameMode::Versus } else { GameMode::Classic };
+        GameState {
+            view: BoardView::fit(level.size),
+            sim: Simulation::with_players(level, seed, players),
+            mode,
+            controllers: Vec::new(),
+            kinds: Vec::new(),
+            assisted: false,
+            inputs: Vec::new(),
+        }
+    }
 
-impl Snake {
-    pub fn new(pos: GridPos) -> Self {
-        let mut body = VecDeque::new();
-       
-        body.push_back(Segment::new((pos.x - 1, pos.y).into()));
-        Snake {
-            head: Segment::new(pos),
-            dir: Direction::Right,
-            last_update_dir: Direction::Right,
-            body,
-            ate: None,
-            next_dir: None,
+    /// Hands `player`'s snake to a controller of `kind`.
+    pub fn set_controller(&mut self, player: usize, kind: ControllerKind) {
+        if player >= self.kinds.len() {
+            return;
         }
+        if player == 0 && kind != ControllerKind::Keyboard {
+            self.assisted = true;
+        }
+        self.kinds[player] = kind;
+        self.controllers[player] = kind.build();
+    }
+
+    /// Switches the first snake to the next controller, from the keyboard
+    /// through each AI and back.
+    pub fn cycle_autopilot(&mut self) {
+        let all = ControllerKind::ALL;
+        let next = all.iter().position(|&kind| kind == self.kinds[0]).map_or(0, |i| (i + 1) % all.len());
+        self.set_controller(0, all[next]);
+    }
+
+    /// Whether an AI drove the first snake at any point.
+    pub fn is_assisted(&self) -> bool {
+        self.assisted
+    }
+
+    /// Ticks per second, following [`Simulation::speed`].
+    fn fps(&self) -> u32 {
+        (self.sim.speed().round() as u32).max(1)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.sim.seed()
+    }
+
+    pub fn sim(&self) -> &Simulation {
+        &self.sim
+    }
+
+    pub fn mode(&self) -> GameMode {
+        self.mode
     }
 
-    fn eats(&self, food: &Food) -> bool {
-        self.head.pos == food.pos
+    pub fn view(&self) -> &BoardView {
+        &self.view
     }
 
-    fn eats_self(&self) -> bool {
-        for seg in &self.body {
-            if self.head.pos == seg.pos {
-                return true;
+    pub fn is_over(&self) -> bool {
+        self.sim.is_over()
+    }
+
+    /// The game played so far, ready to be saved or watched again.
+    pub fn replay(&self) -> Replay {
+        Replay {
+            level: self.sim.level().clone(),
+            seed: self.sim.seed(),
+            players: self.sim.players().len(),
+            turn_buffer: self.sim.turn_buffer(),
+            ticks: self.sim.ticks(),
+            inputs: self.inputs.clone(),
+        }
+    }
+
+    pub fn is_won(&self) -> bool {
+        self.sim.is_won()
+    }
+
+    /// Plays the ticks that are due and sends each one to `broadcast`'s
+    /// spectators, if given.
+    pub fn update(&mut self, ctx: &mut Context, audio: &mut AudioManager, mut broadcast: Option<&mut Broadcast>) -> GameResult {
+
+        while ctx.time.check_update_time(self.fps()) {
+
+            for (player, controller) in self.controllers.iter_mut().enumerate() {
+                let Some(state) = self.sim.players().get(player).filter(|p| p.is_alive()) else {
+                    continue;
+                };
+                // Going straight on needs no turn, which keeps replays short.
+                if let Some(dir) = controller.decide(&self.sim, player)
+                    && dir != state.snake().dir
+                    && self.sim.steer_player(player, dir)
+                {
+                    self.inputs.push(Input { tick: self.sim.ticks(), player, dir });
+                }
+            }
+
+            let headings: Vec<Direction> = self.sim.players().iter().map(|p| p.snake().dir).collect();
+            play_sounds(&self.sim.tick(), audio, ctx);
+            if let Some(broadcast) = broadcast.as_deref_mut() {
+                // Spectators get the turn each snake made this tick, as the
+                // rest of its queue is played on later ticks.
+                let turns: Vec<Option<Direction>> = self.sim.players().iter().zip(headings)
+                    .map(|(p, heading)| Some(p.snake().dir).filter(|&dir| dir != heading))
+                    .collect();
+                broadcast.tick(&turns, &self.sim);
             }
         }
-        false
+
+        Ok(())
     }
 
-    fn update(&mut self, food: &Food) {
-      
-        if self.last_update_dir == self.dir && self.next_dir.is_some() {
-            self.dir = self.next_dir.unwrap();
-            self.next_dir = None;
+    /// Plays one tick with turns made elsewhere, one per player, as when
+    /// watching someone else's game.
+    pub fn follow(&mut self, turns: &[Option<Direction>]) -> Vec<TickOutcome> {
+        for (player, &dir) in turns.iter().enumerate() {
+            if let Some(dir) = dir {
+                self.inputs.push(Input { tick: self.sim.ticks(), player, dir });
+            }
         }
-      
-        let new_head_pos = GridPos::new_from_move(self.head.pos, self.dir);
-       
-        let new_head = Segment::new(new_head_pos);
-        
-        self.body.push_front(self.head);
-        
-        self.head = new_head;
-       
-        if self.eats_self() {
-            self.ate = Some(Ate::Itself);
-        } else if self.eats(food) {
-            self.ate = Some(Ate::Food);
-        } else {
-            self.ate = None;
+        net::play(&mut self.sim, turns)
+    }
+
+    /// Lets time pass without advancing the game, so that resuming after a
+    /// pause does not catch up on every tick that was missed.
+    pub fn hold(&self, ctx: &mut Context) {
+        while ctx.time.check_update_time(self.fps()) {}
+    }
+
+    /// Draws the board and HUD onto `canvas` without finishing it.
+    pub fn draw_board(&self, canvas: &mut graphics::Canvas) {
+        draw_sim(&self.sim, &self.kinds, &self.view, canvas);
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context) -> GameResult {
+
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
+
+        self.draw_board(&mut canvas);
+
+        canvas.finish(ctx)?;
+
+        ggez::timer::yield_now();
+
+        Ok(())
+    }
+
+    pub fn key_down_event(&mut self, key: KeyCode, controls: &Controls) {
+        let Some(action) = controls.action(key, Scope::Game) else {
+            return;
+        };
+        if action == Action::Autopilot && self.mode == GameMode::Classic {
+            self.cycle_autopilot();
+            return;
         }
-        
-        if self.ate.is_none() {
-            self.body.pop_back();
+        // Presses go straight into the snake's turn queue; the AIs steer
+        // on their own.
+        if let Some((player, dir)) = action.direction()
+            && self.kinds.get(player) == Some(&ControllerKind::Keyboard)
+            && !self.sim.is_over()
+            && self.sim.steer_player(player, dir)
+        {
+            self.inputs.push(Input { tick: self.sim.ticks(), player, dir });
         }
-        
-        self.last_update_dir = self.dir;
-    }
-
-    fn draw(&self, canvas: &mut graphics::Canvas) {
-        
-        for seg in &self.body {
-         
-            canvas.draw(
-                &graphics::Quad,
-                graphics::DrawParam::new()
-                    .dest_rect(seg.pos.into())
-                    .color(Color::from_rgb(92, 43, 117)),
-            );
+    }
+}
+
+/// How many ticks Shift+Left/Right jumps in a replay.
+const SEEK_TICKS: u32 = 50;
+
+/// Plays a [`Replay`] back on screen, with a progress bar along the bottom.
+pub struct ReplayState {
+    player: ReplayPlayer,
+    view: BoardView,
+}
+
+impl ReplayState {
+
+    pub fn new(replay: Replay) -> Self {
+        ReplayState {
+            view: BoardView::fit(replay.level.size),
+            player: ReplayPlayer::new(replay),
         }
-        
+    }
+
+    pub fn view(&self) -> &BoardView {
+        &self.view
+    }
+
+    pub fn update(&mut self, ctx: &mut Context) {
+        self.player.update(ctx.time.delta().as_secs_f32());
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context, controls: &Controls) -> GameResult {
+
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
+
+        draw_sim(self.player.sim(), &[], &self.view, &mut canvas);
+
+        let (width, height) = self.view.screen_size();
+        let top = height - HUD_HEIGHT;
         canvas.draw(
             &graphics::Quad,
             graphics::DrawParam::new()
-                .dest_rect(self.head.pos.into())
+                .dest_rect(graphics::Rect::new(0.0, top, width, HUD_HEIGHT))
+                .color(Color::new(0.0, 0.0, 0.0, 0.5)),
+        );
+        let progress = self.player.tick() as f32 / self.player.len().max(1) as f32;
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(graphics::Rect::new(0.0, height - 4.0, width * progress.min(1.0), 4.0))
                 .color(Color::from_rgb(236, 64, 122)),
         );
+
+        let text = graphics::Text::new(
+            graphics::TextFragment::new(format!(
+                "{} {}x   {}/{}   Space: play/pause  {}/{}: speed  {}/{}: step  Shift: seek  {}: back",
+                if self.player.is_paused() { "Paused" } else { "Playing" },
+                self.player.rate(),
+                self.player.tick(),
+                self.player.len(),
+                controls.hint(Action::MoveUp),
+                controls.hint(Action::MoveDown),
+                controls.hint(Action::MoveLeft),
+                controls.hint(Action::MoveRight),
+                controls.hint(Action::Back),
+            ))
+            .scale(18.0),
+        );
+        canvas.draw(
+            &text,
+            graphics::DrawParam::new()
+                .dest(ggez::glam::Vec2::new(8.0, top + 4.0))
+                .color(Color::WHITE),
+        );
+
+        canvas.finish(ctx)
+    }
+
+    /// The menu moves change the speed and step; Space, Home and End are
+    /// fixed.
+    pub fn key_down_event(&mut self, input: KeyInput, controls: &Controls) {
+        let Some(key) = input.keycode else {
+            return;
+        };
+        let player = &mut self.player;
+        let shift = input.mods.contains(KeyMods::SHIFT);
+        match (key, controls.action(key, Scope::Menu)) {
+            (KeyCode::Space, _) => player.toggle_pause(),
+            (KeyCode::Home, _) => player.seek(0),
+            (KeyCode::End, _) => player.seek(player.len()),
+            (_, Some(Action::MoveUp)) => player.change_rate(true),
+            (_, Some(Action::MoveDown)) => player.change_rate(false),
+            (_, Some(Action::MoveLeft)) if shift => player.seek(player.tick().saturating_sub(SEEK_TICKS)),
+            (_, Some(Action::MoveRight)) if shift => player.seek(player.tick() + SEEK_TICKS),
+            (_, Some(Action::MoveLeft)) => player.step(false),
+            (_, Some(Action::MoveRight)) => player.step(true),
+            _ => {}
+        }
     }
 }
 
-pub struct GameState {
-    snake: Snake,
-    food: Food,
-    rng: Rand32,
-    gameover: bool,
+/// The AIs that take turns playing the demo.
+const DEMO_PLAYERS: [ControllerKind; 3] = [ControllerKind::Greedy, ControllerKind::Bfs, ControllerKind::Hamiltonian];
+
+/// How long a finished demo game stays on screen before the next starts.
+const DEMO_RESTART_SECS: f32 = 2.0;
+
+/// Game time after which a demo game is cut short, so the cycle AI does
+/// not keep one level on screen for hours.
+const DEMO_MAX_SECS: f32 = 90.0;
+
+/// A silent game played by the AIs on their own, behind the main menu and
+/// as the attract mode. Each game picks a random level and AI.
+pub struct DemoState {
+    levels: Vec<Level>,
+    sim: Simulation,
+    kind: ControllerKind,
+    controller: [Box<dyn SnakeController>; 1],
+    view: BoardView,
+    /// Real time owed to the simulation, or spent on the finished game.
+    clock: f32,
 }
 
-impl GameState {
-    
-    pub fn new() -> Self {
-     
-       let snake_pos = (GRID_SIZE.0 / 4, GRID_SIZE.1 / 2).into();
-        
-        let mut seed = [0u8; 8];
-        let _ = rand::rngs::OsRng.try_fill_bytes(&mut seed);
-        let mut rng = Rand32::new(u64::from_ne_bytes(seed));
-        
-        let food_pos = GridPos::random(&mut rng, GRID_SIZE.0, GRID_SIZE.1);
+impl DemoState {
 
-        GameState {
-            snake: Snake::new(snake_pos),
-            food: Food::new(food_pos),
-            gameover: false,
-            rng,
+    /// Falls back to the built-in levels when `levels` is empty.
+    pub fn new(mut levels: Vec<Level>) -> Self {
+        if levels.is_empty() {
+            levels = Level::basic_levels();
+        }
+        let seed = random_seed();
+        let level = levels[(seed % levels.len() as u64) as usize].clone();
+        let kind = DEMO_PLAYERS[(seed / levels.len() as u64 % DEMO_PLAYERS.len() as u64) as usize];
+        DemoState {
+            view: BoardView::fit(level.size),
+            sim: Simulation::with_players(level, seed, 1),
+            kind,
+            controller: [kind.build()],
+            levels,
+            clock: 0.0,
         }
     }
 
-    pub fn update(&mut self, ctx: &mut Context, audio: &mut AudioManager, app_state: &mut AppScene) -> GameResult {
-        
-        while ctx.time.check_update_time(DESIRED_FPS) {
-            
-            if !self.gameover {
-                
-                self.snake.update(&self.food);
-                
-                if let Some(ate) = self.snake.ate {
-                    
-                    match ate {
-            
-                       Ate::Food => {
-                            audio.play_sfx("eat", ctx);
-                            let new_food_pos =
-                                GridPos::random(&mut self.rng, GRID_SIZE.0, GRID_SIZE.1);
-                            self.food.pos = new_food_pos;
-                        }
-                        
-                        Ate::Itself => {
-                            audio.play_sfx("die", ctx);
-                            *app_state = AppScene::GameOver;
-                            self.gameover = true;
-                        }
-                    }
-                }
+    fn is_finished(&self) -> bool {
+        self.sim.is_over() || self.sim.elapsed_secs() >= DEMO_MAX_SECS
+    }
+
+    /// Plays as many ticks as fit into `secs` of real time, and starts a new
+    /// game a little while after one ends.
+    pub fn update(&mut self, secs: f32) {
+        self.clock += secs;
+        if self.is_finished() {
+            if self.clock >= DEMO_RESTART_SECS {
+                *self = DemoState::new(std::mem::take(&mut self.levels));
             }
+            return;
         }
+        loop {
+            let tick_len = 1.0 / self.sim.speed();
+            if self.clock < tick_len {
+                break;
+            }
+            self.clock -= tick_len;
+            controller::steer_all(&mut self.sim, &mut self.controller);
+            self.sim.tick();
+            if self.is_finished() {
+                self.clock = 0.0;
+                break;
+            }
+        }
+    }
 
-        Ok(())
+    /// Draws the game stretched over the whole window, whatever its size.
+    pub fn draw_board(&self, ctx: &Context, canvas: &mut graphics::Canvas) {
+        let (width, height) = self.view.screen_size();
+        canvas.set_screen_coordinates(graphics::Rect::new(0.0, 0.0, width, height));
+        draw_sim(&self.sim, &[self.kind], &self.view, canvas);
+        let (width, height) = ctx.gfx.drawable_size();
+        canvas.set_screen_coordinates(graphics::Rect::new(0.0, 0.0, width, height));
     }
 
     pub fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        
+
         let mut canvas =
             graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
 
-        self.snake.draw(&mut canvas);
-        self.food.draw(&mut canvas);
-  
-        canvas.finish(ctx)?;
-   
-        ggez::timer::yield_now();
-        
+        self.draw_board(ctx, &mut canvas);
+
+        let (width, height) = ctx.gfx.drawable_size();
+        let top = height - HUD_HEIGHT;
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(graphics::Rect::new(0.0, top, width, HUD_HEIGHT))
+                .color(Color::new(0.0, 0.0, 0.0, 0.5)),
+        );
+        let text = graphics::Text::new(
+            graphics::TextFragment::new(format!(
+                "Demo: {} on {}   Press any key",
+                self.kind.label(),
+                self.sim.level().name,
+            ))
+            .scale(18.0),
+        );
+        canvas.draw(
+            &text,
+            graphics::DrawParam::new()
+                .dest(ggez::glam::Vec2::new(8.0, top + 4.0))
+                .color(Color::WHITE),
+        );
+
+        canvas.finish(ctx)
+    }
+}
+
+/// A game played with others over the network, from the lobby to the end.
+pub struct NetState {
+    session: Session,
+    /// Set once the host has started the game and its board is known.
+    view: Option<BoardView>,
+}
+
+impl NetState {
+
+    pub fn new(session: Session) -> Self {
+        NetState { session, view: None }
+    }
+
+    /// Plays the ticks that are ready and sends each one to `broadcast`'s
+    /// spectators, if given.
+    pub fn update(&mut self, ctx: &mut Context, audio: &mut AudioManager, mut broadcast: Option<&mut Broadcast>) -> GameResult {
+        self.session.poll();
+        if self.view.is_none()
+            && let Some(sim) = self.session.sim()
+        {
+            let view = BoardView::fit(sim.level().size);
+            view.resize_window(ctx)?;
+            self.view = Some(view);
+        }
+
+        if self.session.is_host() {
+            // The host sets the pace; clients play each tick as it arrives.
+            let fps = self.session.sim().map_or(1, |sim| (sim.speed().round() as u32).max(1));
+            while ctx.time.check_update_time(fps) {
+                let Some(outcomes) = self.session.advance() else {
+                    // Waiting for a player's turn; do not rush the ticks
+                    // that were missed meanwhile.
+                    while ctx.time.check_update_time(fps) {}
+                    break;
+                };
+                play_sounds(&outcomes, audio, ctx);
+                self.broadcast(broadcast.as_deref_mut());
+            }
+        } else {
+            while let Some(outcomes) = self.session.advance() {
+                play_sounds(&outcomes, audio, ctx);
+                self.broadcast(broadcast.as_deref_mut());
+            }
+        }
         Ok(())
     }
 
-    pub fn key_down_event(&mut self, _ctx: &mut Context, input: KeyInput, _repeat: bool) -> GameResult {
-       
-        if let Some(dir) = input.keycode.and_then(Direction::from_keycode) {
-          
-            if self.snake.dir != self.snake.last_update_dir && dir.inverse() != self.snake.dir {
-                self.snake.next_dir = Some(dir);
-            } else if dir.inverse() != self.snake.last_update_dir {
-              
-                self.snake.dir = dir;
+    fn broadcast(&self, broadcast: Option<&mut Broadcast>) {
+        if let (Some(broadcast), Some(sim)) = (broadcast, self.session.sim()) {
+            broadcast.tick(self.session.last_turns(), sim);
+        }
+    }
+
+    fn draw_lines(canvas: &mut graphics::Canvas, title: &str, lines: &[String]) {
+        let text = graphics::Text::new(graphics::TextFragment::new(title).scale(45.0));
+        canvas.draw(&text, graphics::DrawParam::new().dest(ggez::glam::Vec2::new(40.0, 30.0)).color(Color::WHITE));
+        let mut y = 95.0;
+        for line in lines {
+            let text = graphics::Text::new(graphics::TextFragment::new(line.as_str()).scale(25.0));
+            canvas.draw(&text, graphics::DrawParam::new().dest(ggez::glam::Vec2::new(40.0, y)).color(Color::WHITE));
+            y += 32.0;
+        }
+    }
+
+    pub fn draw(&mut self, ctx: &mut Context, controls: &Controls) -> GameResult {
+
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
+
+        let back = format!("{}: back to menu", controls.hint(Action::Back));
+        let session = &self.session;
+        let (Some(sim), Some(view)) = (session.sim(), &self.view) else {
+            let lines = if session.is_host() {
+                vec![
+                    format!("Hosting on port {}", session.port()),
+                    format!("Players: {}", session.players()),
+                    format!("{}: start the game   {}", controls.hint(Action::Confirm), back),
+                ]
+            } else {
+                vec![
+                    format!("Players: {}", session.players()),
+                    "Waiting for the host to start".to_string(),
+                    back,
+                ]
+            };
+            let title = match session.status() {
+                Status::Disconnected(reason) => reason.clone(),
+                _ => "Online lobby".to_string(),
+            };
+            NetState::draw_lines(&mut canvas, &title, &lines);
+            return canvas.finish(ctx);
+        };
+
+        draw_sim(sim, &[], view, &mut canvas);
+        let title = match session.status() {
+            Status::Lobby | Status::Running => None,
+            Status::Over => Some(match sim.winner() {
+                Some(i) if i == session.player() => "You win!".to_string(),
+                Some(i) => format!("Player {} wins!", i + 1),
+                None => "Draw!".to_string(),
+            }),
+            Status::Desync(tick) => Some(format!("Out of sync after tick {}", tick)),
+            Status::Disconnected(reason) => Some(reason.clone()),
+        };
+        match title {
+            Some(title) => {
+                menu::dim(ctx, &mut canvas);
+                NetState::draw_lines(&mut canvas, &title, &[back]);
+            }
+            None => {
+                let caption = graphics::Text::new(
+                    graphics::TextFragment::new(format!("Online as Player {}", session.player() + 1)).scale(18.0),
+                );
+                let (_, height) = view.screen_size();
+                canvas.draw(
+                    &caption,
+                    graphics::DrawParam::new()
+                        .dest(ggez::glam::Vec2::new(8.0, height - 24.0))
+                        .color(Color::new(1.0, 1.0, 1.0, 0.7)),
+                );
+            }
+        }
+
+        canvas.finish(ctx)
+    }
+
+    /// Confirm starts the game from the host's lobby; the first player's
+    /// moves steer the local snake.
+    pub fn key_down_event(&mut self, key: KeyCode, controls: &Controls) {
+        if controls.is(key, Action::Confirm) && self.session.is_host() && *self.session.status() == Status::Lobby {
+            self.session.start(random_seed());
+        } else if let Some((0, dir)) = controls.action(key, Scope::Game).and_then(Action::direction) {
+            self.session.press(dir);
+        }
+    }
+}
+
+/// Watches a game someone else is playing, through a [`Spectator`]
+/// connection. Nothing can be steered.
+pub struct WatchState {
+    spectator: Spectator,
+    game: Option<GameState>,
+    /// Why watching stopped early.
+    problem: Option<String>,
+}
+
+impl WatchState {
+
+    pub fn new(spectator: Spectator) -> Self {
+        WatchState { spectator, game: None, problem: None }
+    }
+
+    pub fn update(&mut self, ctx: &mut Context, audio: &mut AudioManager) -> GameResult {
+        let feed = self.spectator.poll();
+        // Late spectators get the whole game so far at once; only the
+        // latest tick makes a sound.
+        let last_tick = feed.iter().rposition(|item| matches!(item, Feed::Tick { .. }));
+        for (i, item) in feed.into_iter().enumerate() {
+            match item {
+                Feed::Start { level, seed, players } => {
+                    let game = GameState::spectating(level, seed, players);
+                    game.view().resize_window(ctx)?;
+                    self.game = Some(game);
+                    self.problem = None;
+                }
+                Feed::Tick { tick, turns, checksum } => {
+                    let Some(game) = self.game.as_mut().filter(|_| self.problem.is_none()) else {
+                        continue;
+                    };
+                    if game.sim().ticks() != tick {
+                        self.problem = Some(format!("Missed the ticks before {}", tick));
+                        continue;
+                    }
+                    let outcomes = game.follow(&turns);
+                    if game.sim().checksum() != checksum {
+                        self.problem = Some(format!("Out of sync after tick {}", tick));
+                    }
+                    if Some(i) == last_tick {
+                        play_sounds(&outcomes, audio, ctx);
+                    }
+                }
             }
         }
+        if !self.spectator.is_open() && self.problem.is_none() {
+            self.problem = Some("The broadcast ended".to_string());
+        }
         Ok(())
     }
+
+    pub fn draw(&mut self, ctx: &mut Context, controls: &Controls) -> GameResult {
+
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
+
+        let title = match &self.game {
+            Some(game) => {
+                game.draw_board(&mut canvas);
+                match &self.problem {
+                    Some(problem) => Some(problem.clone()),
+                    None if game.is_over() => Some("Game over".to_string()),
+                    None => None,
+                }
+            }
+            None => Some(self.problem.clone().unwrap_or_else(|| "Waiting for a game to start".to_string())),
+        };
+        match title {
+            Some(title) => {
+                menu::dim(ctx, &mut canvas);
+                NetState::draw_lines(&mut canvas, &title, &[format!("{}: back to menu", controls.hint(Action::Back))]);
+            }
+            None => {
+                let caption = graphics::Text::new(graphics::TextFragment::new("Spectating").scale(18.0));
+                let height = ctx.gfx.drawable_size().1;
+                canvas.draw(
+                    &caption,
+                    graphics::DrawParam::new()
+                        .dest(ggez::glam::Vec2::new(8.0, height - 24.0))
+                        .color(Color::new(1.0, 1.0, 1.0, 0.7)),
+                );
+            }
+        }
+
+        canvas.finish(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demo_plays_the_built_in_levels_without_any_others() {
+        let mut demo = DemoState::new(Vec::new());
+        assert!(!demo.levels.is_empty());
+        demo.update(1.0);
+    }
 }
@@ -1,48 +1,629 @@
-use crate::game::GridPos;
+use std::{fmt, fs, path::{Path, PathBuf}};
 
-#[derive(Debug, Clone)]
-pub struct LevelSelect {
-    levels: Vec<Level>,
+use crate::sim::{Direction, FoodKind, GridPos};
+
+/// Board size of the built-in levels.
+pub const DEFAULT_GRID_SIZE: (i32, i32) = (30, 20);
+
+/// Spawn weights for each [`FoodKind`], in the order of [`FoodKind::ALL`],
+/// used by levels that do not set their own.
+pub const DEFAULT_FOOD_WEIGHTS: [u32; 5] = [10, 2, 1, 1, 1];
+
+/// Smallest and largest board side a level file may ask for.
+pub const MIN_GRID_SIDE: i32 = 5;
+pub const MAX_GRID_SIDE: i32 = 100;
+/// Slowest and fastest starting speed a level can have, in ticks per second.
+pub const MIN_LEVEL_SPEED: f32 = 1.0;
+pub const MAX_LEVEL_SPEED: f32 = 30.0;
+
+/// What happens when the snake moves past the edge of the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Both,
+    Off,
+    Horizontal,
+    Vertical,
+}
+
+impl WrapMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "both" => Some(WrapMode::Both),
+            "off" | "none" => Some(WrapMode::Off),
+            "horizontal" => Some(WrapMode::Horizontal),
+            "vertical" => Some(WrapMode::Vertical),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WrapMode::Both => "both",
+            WrapMode::Off => "off",
+            WrapMode::Horizontal => "horizontal",
+            WrapMode::Vertical => "vertical",
+        }
+    }
+}
+
+fn parse_direction(s: &str) -> Option<Direction> {
+    match s {
+        "up" => Some(Direction::Up),
+        "down" => Some(Direction::Down),
+        "left" => Some(Direction::Left),
+        "right" => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+fn direction_name(dir: Direction) -> &'static str {
+    match dir {
+        Direction::Up => "up",
+        Direction::Down => "down",
+        Direction::Left => "left",
+        Direction::Right => "right",
+    }
 }
 
-impl LevelSelect {
-    pub fn new() -> Self {
-        Self { levels: Level::basic_levels() }
+/// Parses `normal=10 bonus=2 ...`; kinds that are left out get weight 0.
+fn parse_food_weights(s: &str) -> Result<[u32; 5], String> {
+    let mut weights = [0; 5];
+    for entry in s.split_whitespace() {
+        let parsed = entry.split_once('=').and_then(|(kind, weight)| {
+            Some((FoodKind::parse(kind)?, weight.parse::<u32>().ok()?))
+        });
+        let Some((kind, weight)) = parsed else {
+            return Err(format!(
+                "food weights look like `normal=10 bonus=2 shrink=1 speedup=1 slowdown=1`, found `{}`",
+                entry
+            ));
+        };
+        weights[kind as usize] = weight;
     }
+    Ok(weights)
+}
+
+fn food_weights_string(weights: &[u32; 5]) -> String {
+    FoodKind::ALL
+        .iter()
+        .zip(weights)
+        .map(|(kind, weight)| format!("{}={}", kind.name(), weight))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
+/// A level file that could not be read, with the line at fault if known.
 #[derive(Debug, Clone)]
+pub struct LevelError {
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl LevelError {
+    fn new(file: &str, line: Option<usize>, message: impl Into<String>) -> Self {
+        Self { file: file.to_string(), line, message: message.into() }
+    }
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Level {
-    pub name: &'static str,
+    pub name: String,
     pub obstacles: Vec<GridPos>,
     pub speed: f32,
+    pub size: (i32, i32),
+    pub wrap: WrapMode,
+    pub spawn: GridPos,
+    pub direction: Direction,
+    pub food: Option<GridPos>,
+    /// Relative chance of each [`FoodKind`] being spawned, indexed like
+    /// [`FoodKind::ALL`].
+    pub food_weights: [u32; 5],
+    /// The file the level was loaded from, so the editor saves over it.
+    pub path: Option<PathBuf>,
 }
 
 impl Level {
+    fn basic(name: &str, obstacles: Vec<GridPos>, speed: f32) -> Self {
+        Level {
+            name: name.to_string(),
+            obstacles,
+            speed,
+            size: DEFAULT_GRID_SIZE,
+            wrap: WrapMode::Both,
+            spawn: GridPos::new(DEFAULT_GRID_SIZE.0 / 4, DEFAULT_GRID_SIZE.1 / 2),
+            direction: Direction::Right,
+            food: None,
+            food_weights: DEFAULT_FOOD_WEIGHTS,
+            path: None,
+        }
+    }
+
     pub fn basic_levels() -> Vec<Self> {
         vec![
-            Level {
-                name: "Easy",
-                obstacles: vec![],
-                speed: 5.0,
-            },
-            Level {
-                name: "Medium",
-                obstacles: vec![
+            Level::basic("Easy", vec![], 5.0),
+            Level::basic(
+                "Medium",
+                vec![
                     GridPos::new(10, 10),
                     GridPos::new(11, 10),
                     GridPos::new(12, 10),
                 ],
-                speed: 8.0,
-            },
-            Level {
-                name: "Hard",
-                obstacles: (5..15)
+                8.0,
+            ),
+            Level::basic(
+                "Hard",
+                (5..15)
                     .map(|x| GridPos::new(x, 7))
                     .chain((7..12).map(|y| GridPos::new(5, y)))
                     .collect(),
-                speed: 12.0,
-            },
+                12.0,
+            ),
         ]
     }
-}
\ No newline at end of file
+
+    /// Reads every `*.lvl` file in `dir`, sorted by file name. Files that fail
+    /// to parse are reported in the second list instead of aborting the load.
+    pub fn load_dir(dir: &Path) -> (Vec<Level>, Vec<LevelError>) {
+        let mut levels = Vec::new();
+        let mut errors = Vec::new();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                errors.push(LevelError::new(&dir.display().to_string(), None, err.to_string()));
+                return (levels, errors);
+            }
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "lvl"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            match Level::load(&path) {
+                Ok(level) => levels.push(level),
+                Err(err) => errors.push(err),
+            }
+        }
+        (levels, errors)
+    }
+
+    /// Checks that the level can actually be played: everything must be on
+    /// the board, the spawn and the cell the snake moves into first must be
+    /// free, and the food must not sit inside a wall or on the spawn.
+    pub fn validate(&self) -> Result<(), String> {
+        let in_bounds = |pos: GridPos| {
+            pos.x >= 0 && pos.y >= 0 && pos.x < self.size.0 && pos.y < self.size.1
+        };
+        if !in_bounds(self.spawn) {
+            return Err("spawn is outside the board".to_string());
+        }
+        if let Some(pos) = self.obstacles.iter().find(|&&pos| !in_bounds(pos)) {
+            return Err(format!("wall at {},{} is outside the board", pos.x, pos.y));
+        }
+        if self.food.is_some_and(|pos| !in_bounds(pos)) {
+            return Err("food is outside the board".to_string());
+        }
+        if self.obstacles.contains(&self.spawn) {
+            return Err("spawn is inside a wall".to_string());
+        }
+        let ahead = GridPos::new_from_move(self.spawn, self.direction, self.size, self.wrap);
+        if ahead.is_none_or(|pos| self.obstacles.contains(&pos)) {
+            return Err("spawn is blocked: the snake crashes on its first move".to_string());
+        }
+        if let Some(food) = self.food && self.obstacles.contains(&food) {
+            return Err("food is inside a wall".to_string());
+        }
+        if self.food == Some(self.spawn) {
+            return Err("food is on the spawn".to_string());
+        }
+        Ok(())
+    }
+
+    /// Where each of `count` snakes starts, with the way it faces.
+    ///
+    /// The first snake uses the level's own spawn. The next ones mirror it
+    /// through the centre of the board, then across the vertical and the
+    /// horizontal axis. When a mirrored spot is blocked by a wall, the food
+    /// or an earlier snake, the first free spot in reading order is used.
+    pub fn spawns(&self, count: usize) -> Vec<(GridPos, Direction)> {
+        let (w, h) = self.size;
+        let (x, y) = (self.spawn.x, self.spawn.y);
+        let flip_x = |dir: Direction| match dir {
+            Direction::Left | Direction::Right => dir.inverse(),
+            _ => dir,
+        };
+        let flip_y = |dir: Direction| match dir {
+            Direction::Up | Direction::Down => dir.inverse(),
+            _ => dir,
+        };
+        let mirrors = [
+            (GridPos::new(x, y), self.direction),
+            (GridPos::new(w - 1 - x, h - 1 - y), self.direction.inverse()),
+            (GridPos::new(w - 1 - x, y), flip_x(self.direction)),
+            (GridPos::new(x, h - 1 - y), flip_y(self.direction)),
+        ];
+
+        // Head and tail, the two cells a new snake covers.
+        let cells = |pos: GridPos, dir: Direction| {
+            let tail = GridPos::new_from_move(pos, dir.inverse(), self.size, WrapMode::Both).unwrap_or(pos);
+            [pos, tail]
+        };
+        let mut taken: Vec<GridPos> = self.obstacles.iter().copied().chain(self.food).collect();
+        let mut spawns = Vec::with_capacity(count);
+        for i in 0..count {
+            let (pos, dir) = mirrors[i % mirrors.len()];
+            let free = |pos: GridPos| !cells(pos, dir).iter().any(|cell| taken.contains(cell));
+            let pos = if i == 0 || free(pos) {
+                pos
+            } else {
+                (0..h)
+                    .flat_map(|y| (0..w).map(move |x| GridPos::new(x, y)))
+                    .find(|&pos| free(pos))
+                    .unwrap_or(pos)
+            };
+            taken.extend(cells(pos, dir));
+            spawns.push((pos, dir));
+        }
+        spawns
+    }
+
+    /// The name in lowercase with anything but letters and digits turned
+    /// into dashes, for use in file names.
+    pub fn slug(&self) -> String {
+        let slug: String = self
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        slug.trim_matches('-').to_string()
+    }
+
+    /// Writes the level in the format read by [`Level::parse`].
+    pub fn to_file_string(&self) -> String {
+        let mut out = format!(
+            "name: {}\nspeed: {}\nsize: {}x{}\nwrap: {}\ndirection: {}\nfood: {}\n---\n",
+            self.name,
+            self.speed,
+            self.size.0,
+            self.size.1,
+            self.wrap.name(),
+            direction_name(self.direction),
+            food_weights_string(&self.food_weights),
+        );
+        for y in 0..self.size.1 {
+            for x in 0..self.size.0 {
+                let pos = GridPos::new(x, y);
+                let c = if pos == self.spawn {
+                    'S'
+                } else if Some(pos) == self.food {
+                    'F'
+                } else if self.obstacles.contains(&pos) {
+                    '#'
+                } else {
+                    '.'
+                };
+                out.push(c);
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn load(path: &Path) -> Result<Level, LevelError> {
+        let file = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let text = fs::read_to_string(path)
+            .map_err(|err| LevelError::new(&file, None, err.to_string()))?;
+        let mut level = Level::parse(&file, &text)?;
+        level.path = Some(path.to_path_buf());
+        Ok(level)
+    }
+
+    /// Parses the level file format: a `key: value` header, a `---` line,
+    /// then the map as rows of `.` (empty), `#` (wall), `S` (snake spawn)
+    /// and `F` (initial food). Only `name` and `speed` are required in the
+    /// header; `food` sets the spawn weight of each food kind. Blank lines
+    /// and lines starting with `;` in the header are ignored.
+    ///
+    /// ```text
+    /// name: Medium
+    /// speed: 8
+    /// size: 30x20
+    /// wrap: both
+    /// direction: right
+    /// food: normal=10 bonus=2 shrink=1 speedup=1 slowdown=1
+    /// ---
+    /// ..............................
+    /// .......S..###.................
+    /// ```
+    ///
+    /// A level that parses but cannot be played, see [`Level::validate`], is
+    /// an error as well.
+    pub fn parse(file: &str, text: &str) -> Result<Level, LevelError> {
+        let err = |line: usize, message: String| LevelError::new(file, Some(line), message);
+
+        let mut name = None;
+        let mut speed = None;
+        let mut size = None;
+        let mut wrap = WrapMode::Both;
+        let mut direction = Direction::Right;
+        let mut food_weights = DEFAULT_FOOD_WEIGHTS;
+
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim_end()));
+        let mut grid_start = None;
+        for (no, line) in lines.by_ref() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if line == "---" {
+                grid_start = Some(no);
+                break;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(err(no, format!("expected `key: value` or `---`, found `{}`", line)));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "name" => {
+                    if value.is_empty() {
+                        return Err(err(no, "name must not be empty".to_string()));
+                    }
+                    name = Some(value.to_string());
+                }
+                "speed" => match value.parse::<f32>() {
+                    Ok(v) if (MIN_LEVEL_SPEED..=MAX_LEVEL_SPEED).contains(&v) => speed = Some(v),
+                    _ => {
+                        return Err(err(
+                            no,
+                            format!("speed must be a number from {} to {}, found `{}`", MIN_LEVEL_SPEED, MAX_LEVEL_SPEED, value),
+                        ));
+                    }
+                },
+                "size" => {
+                    let parsed = value
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.trim().parse::<i32>().ok()?, h.trim().parse::<i32>().ok()?)))
+                        .filter(|&(w, h)| w > 0 && h > 0);
+                    match parsed {
+                        Some(s) => size = Some(s),
+                        None => return Err(err(no, format!("size must look like `30x20`, found `{}`", value))),
+                    }
+                }
+                "wrap" => match WrapMode::parse(value) {
+                    Some(w) => wrap = w,
+                    None => return Err(err(no, format!(
+                        "wrap must be one of both, off, horizontal, vertical, found `{}`", value
+                    ))),
+                },
+                "direction" => match parse_direction(value) {
+                    Some(d) => direction = d,
+                    None => return Err(err(no, format!(
+                        "direction must be one of up, down, left, right, found `{}`", value
+                    ))),
+                },
+                "food" => match parse_food_weights(value) {
+                    Ok(weights) => food_weights = weights,
+                    Err(message) => return Err(err(no, message)),
+                },
+                other => return Err(err(no, format!("unknown header key `{}`", other))),
+            }
+        }
+
+        let Some(grid_start) = grid_start else {
+            return Err(LevelError::new(file, None, "missing `---` line before the map"));
+        };
+        let Some(name) = name else {
+            return Err(LevelError::new(file, None, "missing `name` in header"));
+        };
+        let Some(speed) = speed else {
+            return Err(LevelError::new(file, None, "missing `speed` in header"));
+        };
+
+        let rows: Vec<(usize, &str)> = lines.filter(|(_, line)| !line.is_empty()).collect();
+        let size = size.unwrap_or_else(|| {
+            (rows.first().map_or(0, |(_, row)| row.chars().count() as i32), rows.len() as i32)
+        });
+        if rows.len() as i32 != size.1 {
+            return Err(LevelError::new(file, Some(grid_start), format!(
+                "map has {} rows but size says {}", rows.len(), size.1
+            )));
+        }
+        let side = MIN_GRID_SIDE..=MAX_GRID_SIDE;
+        if !side.contains(&size.0) || !side.contains(&size.1) {
+            return Err(LevelError::new(file, None, format!(
+                "board size {}x{} is out of range, each side must be {} to {}",
+                size.0, size.1, MIN_GRID_SIDE, MAX_GRID_SIDE
+            )));
+        }
+
+        let mut obstacles = Vec::new();
+        let mut spawn = None;
+        let mut food = None;
+        for (y, (no, row)) in rows.iter().enumerate() {
+            if row.chars().count() as i32 != size.0 {
+                return Err(err(*no, format!(
+                    "row is {} cells wide but size says {}", row.chars().count(), size.0
+                )));
+            }
+            for (x, c) in row.chars().enumerate() {
+                let pos = GridPos::new(x as i32, y as i32);
+                match c {
+                    '.' => {}
+                    '#' => obstacles.push(pos),
+                    'S' if spawn.is_none() => spawn = Some(pos),
+                    'S' => return Err(err(*no, "more than one spawn `S`".to_string())),
+                    'F' if food.is_none() => food = Some(pos),
+                    'F' => return Err(err(*no, "more than one food `F`".to_string())),
+                    other => return Err(err(*no, format!("unknown map cell `{}` in column {}", other, x + 1))),
+                }
+            }
+        }
+        let Some(spawn) = spawn else {
+            return Err(LevelError::new(file, None, "map has no spawn `S`"));
+        };
+
+        let level = Level { name, obstacles, speed, size, wrap, spawn, direction, food, food_weights, path: None };
+        level.validate().map_err(|message| LevelError::new(file, None, message))?;
+        Ok(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "name: Test\nspeed: 5\n";
+
+    fn parse(text: &str) -> Result<Level, LevelError> {
+        Level::parse("test.lvl", text)
+    }
+
+    fn error(text: &str) -> LevelError {
+        parse(text).expect_err("level should not parse")
+    }
+
+    #[test]
+    fn parses_a_level() {
+        let text = format!("{}size: 6x5\nwrap: off\ndirection: up\n---\n......\n.#....\n..S...\n....F.\n......\n", HEADER);
+        let level = parse(&text).unwrap();
+        assert_eq!(level.name, "Test");
+        assert_eq!(level.speed, 5.0);
+        assert_eq!(level.size, (6, 5));
+        assert_eq!(level.wrap, WrapMode::Off);
+        assert_eq!(level.direction, Direction::Up);
+        assert_eq!(level.obstacles, [GridPos::new(1, 1)]);
+        assert_eq!(level.spawn, GridPos::new(2, 2));
+        assert_eq!(level.food, Some(GridPos::new(4, 3)));
+        assert_eq!(level.food_weights, DEFAULT_FOOD_WEIGHTS);
+    }
+
+    #[test]
+    fn file_string_parses_back() {
+        for level in Level::basic_levels() {
+            let parsed = parse(&level.to_file_string()).unwrap();
+            assert_eq!(parsed.to_file_string(), level.to_file_string());
+        }
+    }
+
+    #[test]
+    fn shipped_levels_load() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assert/levels");
+        let (levels, errors) = Level::load_dir(&dir);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(!levels.is_empty());
+    }
+
+    #[test]
+    fn missing_separator() {
+        let err = error(&format!("{}.....\n..S..\n", HEADER));
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.contains("`---`"), "{}", err);
+
+        let err = error(HEADER);
+        assert_eq!(err.line, None);
+        assert!(err.message.contains("missing `---`"), "{}", err);
+    }
+
+    #[test]
+    fn ragged_rows() {
+        let err = error(&format!("{}---\n.....\n..S..\n....\n.....\n.....\n", HEADER));
+        assert_eq!(err.line, Some(6));
+        assert!(err.message.contains("4 cells wide"), "{}", err);
+
+        let err = error(&format!("{}size: 5x5\n---\n.....\n..S..\n.....\n", HEADER));
+        assert!(err.message.contains("3 rows"), "{}", err);
+    }
+
+    #[test]
+    fn unknown_glyph() {
+        let err = error(&format!("{}---\n.....\n..S..\n..x..\n.....\n.....\n", HEADER));
+        assert_eq!(err.line, Some(6));
+        assert!(err.message.contains("`x` in column 3"), "{}", err);
+    }
+
+    #[test]
+    fn missing_spawn() {
+        let err = error(&format!("{}---\n.....\n.....\n.....\n.....\n.....\n", HEADER));
+        assert!(err.message.contains("no spawn"), "{}", err);
+    }
+
+    #[test]
+    fn food_weights() {
+        let map = "---\n.....\n..S..\n.....\n.....\n.....\n";
+        let level = parse(&format!("{}food: bonus=3 shrink=1\n{}", HEADER, map)).unwrap();
+        assert_eq!(level.food_weights, [0, 3, 1, 0, 0]);
+
+        let err = error(&format!("{}food: bonus=lots\n{}", HEADER, map));
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.contains("`bonus=lots`"), "{}", err);
+    }
+
+    #[test]
+    fn bad_header() {
+        let map = "---\n.....\n..S..\n.....\n.....\n.....\n";
+        let err = error(&format!("name: Test\nspeed: fast\n{}", map));
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains("speed"), "{}", err);
+
+        for speed in ["inf", "NaN", "0", "-3", "31"] {
+            let err = error(&format!("name: Test\nspeed: {}\n{}", speed, map));
+            assert_eq!(err.line, Some(2));
+            assert!(err.message.contains("speed"), "{}", err);
+        }
+
+        let err = error(&format!("{}colour: red\n{}", HEADER, map));
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.contains("unknown header key `colour`"), "{}", err);
+
+        let err = error(&format!("{}size: big\n{}", HEADER, map));
+        assert!(err.message.contains("size"), "{}", err);
+
+        let err = error(&format!("{}just words\n{}", HEADER, map));
+        assert!(err.message.contains("`key: value`"), "{}", err);
+
+        let err = error(&format!("speed: 5\n{}", map));
+        assert!(err.message.contains("missing `name`"), "{}", err);
+    }
+
+    #[test]
+    fn unplayable_level_is_rejected() {
+        let err = error(&format!("{}wrap: off\n---\n.....\n..S#.\n.....\n.....\n.....\n", HEADER));
+        assert!(err.message.contains("spawn is blocked"), "{}", err);
+
+        let err = error(&format!("{}wrap: off\n---\n.....\n....S\n.....\n.....\n.....\n", HEADER));
+        assert!(err.message.contains("spawn is blocked"), "{}", err);
+    }
+
+    #[test]
+    fn food_on_the_spawn_is_rejected() {
+        let mut level = Level::basic_levels().remove(0);
+        level.food = Some(level.spawn);
+        assert_eq!(level.validate(), Err("food is on the spawn".to_string()));
+    }
+
+    #[test]
+    fn cells_outside_the_board_are_rejected() {
+        let mut level = Level::basic_levels().remove(0);
+        level.obstacles.push(GridPos::new(30, 19));
+        assert!(level.validate().unwrap_err().contains("outside the board"));
+
+        let mut level = Level::basic_levels().remove(0);
+        level.food = Some(GridPos::new(3, -1));
+        assert!(level.validate().unwrap_err().contains("outside the board"));
+    }
+}
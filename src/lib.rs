@@ -0,0 +1,11 @@
+//! The rules of the game and the AIs, without any window or sound, so
+//! tools like `snake-sim` can play it headlessly.
+
+pub mod ai;
+pub mod controller;
+pub mod env;
+pub mod level;
+pub mod net;
+pub mod replay;
+pub mod sim;
+pub mod tournament;
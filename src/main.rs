@@ -1,26 +1,48 @@
 mod menu;
 mod game;
-mod level;
 mod audio;
+mod editor;
+mod scores;
+mod controls;
 
-use std::{env, path};
+use snake::{controller, level, net, replay, sim};
+
+use std::{env, path, time::{SystemTime, UNIX_EPOCH}};
 
 use ggez::{
-    event, glam, 
-    graphics::{self, Color, Drawable, TextFragment}, 
+    event::{self, MouseButton}, glam, 
+    graphics::{self, TextFragment}, 
     input::keyboard::KeyCode,
     Context, GameError, GameResult
 };
-use game::GameState;
-use menu::{MenuManager, MenuState};
+use game::{DemoState, GameMode, GameState, NetState, ReplayState, WatchState};
+use menu::{LevelSelect, MenuManager, MenuState};
 use audio::AudioManager;
+use level::{Level, WrapMode};
+use sim::{Ate, FoodKind};
+use editor::{Editor, EditorAction};
+use scores::{HighScores, ScoreEntry, NAME_LEN};
+use replay::Replay;
+use controller::ControllerKind;
+use controls::{Action, Controls, Scope};
+
+/// How long the main menu waits without a key press before the demo
+/// takes over the screen.
+const DEMO_IDLE_SECS: f32 = 30.0;
 
 #[derive(Debug, Clone, Copy)]
 enum AppScene {
     Menu,
+    Demo,
     Playing,
     Pause,
     GameOver,
+    Editor,
+    Replay,
+    /// A game with other players over the network.
+    Network,
+    /// Someone else's game, watched over the network.
+    Watch,
 }
 
 struct AppState {
@@ -28,46 +50,297 @@ struct AppState {
     menu: MenuManager,
     game: Option<GameState>,
     audio: AudioManager,
+    level: Level,
+    /// The mode new games from the menus are started in.
+    mode: GameMode,
+    /// Who plays the second snake in versus games.
+    opponent: ControllerKind,
+    levels_dir: path::PathBuf,
+    options: Options,
+    editor: Option<Editor>,
+    /// Set while the running game was started from the level editor.
+    playtest: bool,
+    /// Rank the last finished game reached in its high score table.
+    new_record: Option<usize>,
+    /// The name being typed for a run that made the high score table.
+    name_entry: Option<String>,
+    /// The replay being watched.
+    replay: Option<ReplayState>,
+    /// The AI game playing behind the main menu.
+    demo: DemoState,
+    /// Seconds the main menu has been shown without a key press.
+    idle: f32,
+    /// The networked game hosted or joined from the command line.
+    net: Option<NetState>,
+    /// Sends the games played here to spectators.
+    broadcast: Option<net::Broadcast>,
+    /// The game being watched as a spectator.
+    watch: Option<WatchState>,
 }
 
 
 impl AppState {
-    fn new(ctx: &mut Context) -> Self {
+    fn new(ctx: &mut Context, levels_dir: &path::Path, options: Options) -> Self {
         let mut audio = AudioManager::new();
-        let _= audio.load_sfx(ctx, "eat", "eat.ogg");
-        let _= audio.load_sfx(ctx, "die", "die.ogg");
+        // Food whose sound is missing plays "eat" instead.
+        let sounds = [
+            ("eat", "/eat.ogg"),
+            ("die", "/die.ogg"),
+            ("bonus", "/bonus.wav"),
+            ("shrink", "/shrink.wav"),
+            ("speedup", "/speedup.wav"),
+            ("slowdown", "/slowdown.wav"),
+        ];
+        for (name, file) in sounds {
+            if let Err(err) = audio.load_sfx(ctx, name, file) {
+                eprintln!("cannot load sound {}: {}", file, err);
+            }
+        }
 
         let _= audio.play_bgm(ctx, "bgm.mp3", true);
 
+        let levels = LevelSelect::from_dir(levels_dir);
+        let level = levels.levels[0].clone();
+        let demo = DemoState::new(levels.levels.clone());
+        let scores = HighScores::load(&ctx.fs.user_data_dir().join("highscores.txt"));
+        let keys = Controls::load(&ctx.fs.user_data_dir().join("controls.txt"));
+
         Self {
             scene: AppScene::Menu,
-            menu: MenuManager::new(),
-            game: Some(GameState::new()),
+            menu: MenuManager::new(levels, scores, keys),
+            game: None,
             audio,
+            level,
+            mode: GameMode::Classic,
+            opponent: ControllerKind::Keyboard,
+            levels_dir: levels_dir.to_path_buf(),
+            options,
+            editor: None,
+            playtest: false,
+            new_record: None,
+            name_entry: None,
+            replay: None,
+            demo,
+            idle: 0.0,
+            net: None,
+            broadcast: None,
+            watch: None,
+        }
+    }
+
+    /// Saves the replay of the finished game and asks for a name if it made
+    /// its high score table.
+    fn finish_game(&mut self, ctx: &Context) {
+        self.new_record = None;
+        self.name_entry = None;
+        self.menu.game_over.selected = 0;
+        if let Some(game) = &self.game {
+            let dir = ctx.fs.user_data_dir().join("replays");
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            if let Err(err) = game.replay().save_in(&dir, now) {
+                eprintln!("cannot save replay: {}", err);
+            }
+            self.menu.game_over.replay_available = true;
+
+            let sim = game.sim();
+            if game.mode().keeps_scores() && !game.is_assisted() && self.menu.scores.qualifies(&sim.level().name, game.mode().name(), sim.score()) {
+                self.name_entry = Some(String::new());
+            }
+        }
+    }
+
+    /// Puts the finished game into its high score table under `name` and
+    /// saves it.
+    fn record_score(&mut self, name: String) {
+        self.new_record = None;
+        let Some(game) = &self.game else {
+            return;
+        };
+        let sim = game.sim();
+        let entry = ScoreEntry {
+            score: sim.score(),
+            length: sim.snake().len(),
+            secs: sim.elapsed_secs(),
+            seed: sim.seed(),
+            name,
+            date: scores::today(),
+        };
+        let scores = &mut self.menu.scores;
+        self.new_record = scores.insert(&sim.level().name, game.mode().name(), entry);
+        if self.new_record.is_some() && let Err(err) = scores.save() {
+            eprintln!("cannot save high scores: {}", err);
+        }
+    }
+
+    /// Switches to watching `replay` and fits the window to its board.
+    fn watch_replay(&mut self, ctx: &mut Context, replay: Replay) -> GameResult {
+        let state = ReplayState::new(replay);
+        state.view().resize_window(ctx)?;
+        self.replay = Some(state);
+        self.scene = AppScene::Replay;
+        Ok(())
+    }
+
+    /// Hosts a game on `port` or joins the one at `addr`, as asked for on
+    /// the command line, and shows its lobby.
+    fn go_online(&mut self, port: u16, join: Option<&str>) -> GameResult {
+        let session = match join {
+            Some(addr) => net::Session::join(addr),
+            None => net::Session::host(port, self.level.clone(), net::INPUT_DELAY),
+        };
+        let mut session = session.map_err(|err| GameError::CustomError(format!("cannot go online: {}", err)))?;
+        if let Some(depth) = self.options.turn_buffer {
+            session.set_turn_buffer(depth);
+        }
+        self.net = Some(NetState::new(session));
+        self.scene = AppScene::Network;
+        Ok(())
+    }
+
+    /// Lets spectators watch every game played here from now on.
+    fn open_broadcast(&mut self, port: u16) -> GameResult {
+        let broadcast = net::Broadcast::new(port)
+            .map_err(|err| GameError::CustomError(format!("cannot open port {} for spectators: {}", port, err)))?;
+        self.broadcast = Some(broadcast);
+        Ok(())
+    }
+
+    /// Watches the games broadcast at `addr`.
+    fn watch_game(&mut self, addr: &str) -> GameResult {
+        let spectator = net::Spectator::connect(addr)
+            .map_err(|err| GameError::CustomError(format!("cannot watch {}: {}", addr, err)))?;
+        self.watch = Some(WatchState::new(spectator));
+        self.scene = AppScene::Watch;
+        Ok(())
+    }
+
+    /// Goes back to the menus at `state`, with the window back at its usual
+    /// size after a board that may have been smaller or larger.
+    fn show_menu(&mut self, ctx: &mut Context, state: MenuState) -> GameResult {
+        self.menu.state = state;
+        self.scene = AppScene::Menu;
+        ctx.gfx.set_drawable_size(game::SCREEN_SIZE.0, game::SCREEN_SIZE.1)
+    }
+
+    /// Leaves the pause menu and carries on with the frozen game.
+    fn resume(&mut self) {
+        self.audio.resume_bgm();
+        self.scene = AppScene::Playing;
+    }
+
+    /// Starts a new game on the current level in the current mode, applying
+    /// the command-line `--seed`, `--wrap` and `--turn-buffer` values if
+    /// they were given.
+    fn new_game(&mut self, ctx: &mut Context) -> GameResult {
+        self.start_game(ctx, self.level.clone(), self.mode)
+    }
+
+    /// Starts a game on `level` and fits the window to its board.
+    fn start_game(&mut self, ctx: &mut Context, mut level: Level, mode: GameMode) -> GameResult {
+        if let Some(wrap) = self.options.wrap {
+            level.wrap = wrap;
+        }
+        let mut game = match self.options.seed {
+            Some(seed) => GameState::with_seed(level, mode, seed),
+            None => GameState::new(level, mode),
+        };
+        if let Some(depth) = self.options.turn_buffer {
+            game = game.with_turn_buffer(depth);
+        }
+        if mode == GameMode::Versus {
+            game.set_controller(1, self.opponent);
         }
+        game.view().resize_window(ctx)?;
+        self.game = Some(game);
+        Ok(())
     }
 }
 
 impl event::EventHandler<GameError> for AppState {
     fn update(&mut self, ctx: &mut ggez::Context) -> Result<(), GameError> {
+        if let Some(broadcast) = &mut self.broadcast {
+            broadcast.poll();
+        }
         match self.scene {
-            AppScene::Menu => Ok(()),
+            AppScene::Menu => {
+                if matches!(self.menu.state, MenuState::Main) {
+                    let secs = ctx.time.delta().as_secs_f32();
+                    self.demo.update(secs);
+                    self.idle += secs;
+                    if self.idle >= DEMO_IDLE_SECS {
+                        self.scene = AppScene::Demo;
+                    }
+                }
+                Ok(())
+            }
+            AppScene::Demo => {
+                self.demo.update(ctx.time.delta().as_secs_f32());
+                Ok(())
+            }
             AppScene::Playing => {
                 if let Some(game) = &mut self.game {
-                    game.update(ctx, &mut self.audio, &mut self.scene)
-                } else {
-                    Ok(())
+                    game.update(ctx, &mut self.audio, self.broadcast.as_mut())?;
+                    if game.is_over() {
+                        if self.playtest {
+                            self.playtest = false;
+                            self.scene = AppScene::Editor;
+                        } else {
+                            self.finish_game(ctx);
+                            self.scene = AppScene::GameOver;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            AppScene::Pause => {
+                if let Some(game) = &self.game {
+                    game.hold(ctx);
                 }
+                Ok(())
             }
-            AppScene::Pause => Ok(()),
             AppScene::GameOver => Ok(()),
+            AppScene::Editor => Ok(()),
+            AppScene::Replay => {
+                if let Some(replay) = &mut self.replay {
+                    replay.update(ctx);
+                }
+                Ok(())
+            }
+            AppScene::Network => {
+                if let Some(net) = &mut self.net {
+                    net.update(ctx, &mut self.audio, self.broadcast.as_mut())?;
+                }
+                Ok(())
+            }
+            AppScene::Watch => {
+                if let Some(watch) = &mut self.watch {
+                    watch.update(ctx, &mut self.audio)?;
+                }
+                Ok(())
+            }
         }
     }
 
     fn draw(&mut self, ctx: &mut ggez::Context) -> Result<(), GameError> {
         match self.scene {
             AppScene::Menu => {
-                self.menu.draw(ctx)
+                if matches!(self.menu.state, MenuState::Main) {
+                    let mut canvas =
+                            graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
+                    self.demo.draw_board(ctx, &mut canvas);
+                    self.menu.main.draw(ctx, &mut canvas);
+                    canvas.finish(ctx)
+                } else {
+                    self.menu.draw(ctx)
+                }
+            }
+            AppScene::Demo => self.demo.draw(ctx),
+            AppScene::Editor => {
+                if let Some(editor) = &mut self.editor {
+                    editor.draw(ctx)
+                } else {
+                    Ok(())
+                }
             }
             AppScene::Playing => {
                 if let Some(game) = &mut self.game {
@@ -76,35 +349,129 @@ impl event::EventHandler<GameError> for AppState {
                     Ok(())
                 }
             }
+            AppScene::Replay => {
+                if let Some(replay) = &mut self.replay {
+                    replay.draw(ctx, &self.menu.keys)
+                } else {
+                    Ok(())
+                }
+            }
+            AppScene::Network => {
+                if let Some(net) = &mut self.net {
+                    net.draw(ctx, &self.menu.keys)
+                } else {
+                    Ok(())
+                }
+            }
+            AppScene::Watch => {
+                if let Some(watch) = &mut self.watch {
+                    watch.draw(ctx, &self.menu.keys)
+                } else {
+                    Ok(())
+                }
+            }
             AppScene::Pause => {
                 let mut canvas =
                         graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
-                // if let Some(game) = &mut self.game {
-                //     game.draw(&mut canvas);
-                // }   
-                let y = 200.0;
-                let text_c = graphics::Text::new(
-                    TextFragment::new("Continue".to_string()).scale(45.0)
-                );
-                let rect_c = text_c.dimensions(ctx).unwrap();
-                let pos_c = glam::Vec2::new(game::SCREEN_SIZE.0 / 2.0 - rect_c.w / 2.0, y);
-                let text_q = graphics::Text::new(
-                    TextFragment::new("Quit".to_string()).scale(45.0)
-                );
-                let rect_q = text_q.dimensions(ctx).unwrap();
-                let pos_q = glam::Vec2::new(game::SCREEN_SIZE.0 / 2.0 - rect_q.w / 2.0, y + 100.0);
-                canvas.draw(&text_c, graphics::DrawParam::new().dest(pos_c).color(Color::from_rgb(200, 34, 32)));
-                canvas.draw(&text_q, graphics::DrawParam::new().dest(pos_q).color(Color::from_rgb(200, 34, 32)));
+                if let Some(game) = &self.game {
+                    game.draw_board(&mut canvas);
+                }
+                self.menu.pause.draw(ctx, &mut canvas);
                 canvas.finish(ctx)?;
                 Ok(())
             }
             AppScene::GameOver => {
-                let mut canvas = graphics::Canvas::from_frame(ctx, Color::from_rgb(0, 244, 0));
-                let text = graphics::Text::new(
-                    TextFragment::new("Gram Over! Press R to Restart".to_string()).scale(35.0)
-                );
-                let pos = glam::Vec2::new(100.0, 100.0);
-                canvas.draw(&text, graphics::DrawParam::new().dest(pos).color([1.0, 0.0, 0.0, 1.0]));
+                let mut canvas =
+                        graphics::Canvas::from_frame(ctx, graphics::Color::from_rgb(15, 15, 28));
+                let Some(game) = &self.game else {
+                    return canvas.finish(ctx);
+                };
+                game.draw_board(&mut canvas);
+                menu::dim(ctx, &mut canvas);
+
+                let sim = game.sim();
+                let (title, lines) = if game.mode() == GameMode::Versus {
+                    let title = match sim.winner() {
+                        Some(i) => format!("Player {} wins!", i + 1),
+                        None => "Draw!".to_string(),
+                    };
+                    let mut lines = vec![
+                        format!("Level: {}   Seed: {}", sim.level().name, game.seed()),
+                        format!("Time: {}", game::format_time(sim.elapsed_secs())),
+                    ];
+                    let moves = |actions: [Action; 4]| {
+                        let keys: Vec<String> = actions.iter().map(|&action| self.menu.keys.hint(action)).collect();
+                        keys.join("/")
+                    };
+                    let keys = [
+                        moves([Action::MoveUp, Action::MoveLeft, Action::MoveDown, Action::MoveRight]),
+                        moves([Action::P2Up, Action::P2Left, Action::P2Down, Action::P2Right]),
+                    ];
+                    for (i, (player, keys)) in sim.players().iter().zip(keys).enumerate() {
+                        lines.push(format!(
+                            "Player {} ({}): score {}   length {}   {}",
+                            i + 1,
+                            keys,
+                            player.score(),
+                            player.snake().len(),
+                            player.death().map_or("Survived", Ate::describe),
+                        ));
+                    }
+                    (title, lines)
+                } else {
+                    let title = if game.is_won() { "Board full, you win!" } else { "Game Over" };
+                    let cause = if game.is_won() {
+                        "Filled the board"
+                    } else {
+                        sim.death().map_or("", Ate::describe)
+                    };
+                    let eaten: Vec<String> = FoodKind::ALL
+                        .iter()
+                        .map(|&kind| format!("{} {}", kind.name(), sim.eaten(kind)))
+                        .collect();
+                    let lines = vec![
+                        format!("Level: {}   Seed: {}", sim.level().name, game.seed()),
+                        format!("Cause: {}", cause),
+                        format!(
+                            "Score: {}   Length: {}   Time: {}",
+                            sim.score(),
+                            sim.snake().len(),
+                            game::format_time(sim.elapsed_secs())
+                        ),
+                        format!("Food eaten: {}", eaten.join("  ")),
+                    ];
+                    (title.to_string(), lines)
+                };
+                let text = graphics::Text::new(TextFragment::new(title).scale(45.0));
+                canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(40.0, 30.0)).color([1.0, 0.0, 0.0, 1.0]));
+
+                let mut y = 95.0;
+                for line in lines {
+                    let text = graphics::Text::new(TextFragment::new(line).scale(25.0));
+                    canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(40.0, y)).color([1.0, 1.0, 1.0, 1.0]));
+                    y += 32.0;
+                }
+                y += 15.0;
+
+                if let Some(name) = &self.name_entry {
+                    let prompt = graphics::Text::new(
+                        TextFragment::new(format!("New high score! Your name: {}_", name)).scale(30.0)
+                    );
+                    canvas.draw(&prompt, graphics::DrawParam::new().dest(glam::Vec2::new(40.0, y)).color([1.0, 1.0, 0.0, 1.0]));
+                    let hint = graphics::Text::new(
+                        TextFragment::new("Type your name and press Enter".to_string()).scale(22.0)
+                    );
+                    canvas.draw(&hint, graphics::DrawParam::new().dest(glam::Vec2::new(40.0, y + 40.0)).color([1.0, 1.0, 1.0, 0.8]));
+                } else {
+                    if let Some(rank) = self.new_record {
+                        let record = graphics::Text::new(
+                            TextFragment::new(format!("New record! #{} on this level", rank + 1)).scale(30.0)
+                        );
+                        canvas.draw(&record, graphics::DrawParam::new().dest(glam::Vec2::new(40.0, y)).color([1.0, 1.0, 0.0, 1.0]));
+                    }
+                    let height = ctx.gfx.drawable_size().1;
+                    self.menu.game_over.draw(ctx, &mut canvas, height - 70.0);
+                }
                 canvas.finish(ctx)?;
                 Ok(())
             }
@@ -115,65 +482,453 @@ impl event::EventHandler<GameError> for AppState {
             &mut self,
             ctx: &mut ggez::Context,
             input: ggez::input::keyboard::KeyInput,
-            _repeated: bool,
+            repeated: bool,
         ) -> Result<(), GameError> {
+        self.idle = 0.0;
+        let Some(key) = input.keycode else {
+            return Ok(());
+        };
+        let action = self.menu.keys.action(key, Scope::Menu);
         match self.scene {
+            AppScene::Demo => self.scene = AppScene::Menu,
             AppScene::Menu => {
                 match self.menu.state {
                     MenuState::Main => {
                         let main = &mut self.menu.main;
-                        if let Some(code) = input.keycode {
-                            match code {
-                                KeyCode::Up => main.move_selection(true),
-                                KeyCode::Down => main.move_selection(false),
-                                KeyCode::Return => {
-                                    if main.selected == 0 {
+                        match action {
+                            Some(Action::MoveUp) => main.move_selection(true),
+                            Some(Action::MoveDown) => main.move_selection(false),
+                            Some(Action::Confirm) => {
+                                match main.selected_option() {
+                                    "Start Game" => {
+                                        self.mode = GameMode::Classic;
                                         self.scene = AppScene::Playing;
-                                        self.game = Some(GameState::new());
-                                    } else {
-                                        ctx.request_quit();
+                                        self.new_game(ctx)?;
+                                    }
+                                    "Versus" => self.menu.state = MenuState::Opponent,
+                                    "Select Level" => {
+                                        self.mode = GameMode::Classic;
+                                        self.menu.state = MenuState::Level;
+                                    }
+                                    "High Scores" => {
+                                        self.menu.scores_page = self.menu.level.selected;
+                                        self.menu.state = MenuState::Scores;
+                                    }
+                                    "Level Editor" => {
+                                        let editor = Editor::new(self.level.clone());
+                                        editor.view.resize_window(ctx)?;
+                                        self.editor = Some(editor);
+                                        self.scene = AppScene::Editor;
+                                    }
+                                    "Controls" => {
+                                        self.menu.controls = menu::ControlsMenu::new();
+                                        self.menu.state = MenuState::Controls;
+                                    }
+                                    _ => ctx.request_quit(),
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    MenuState::Level => {
+                        let select = &mut self.menu.level;
+                        match action {
+                            Some(Action::MoveUp) => select.move_selection(true),
+                            Some(Action::MoveDown) => select.move_selection(false),
+                            Some(Action::Confirm) => {
+                                if let Some(level) = select.selected_level() {
+                                    self.level = level.clone();
+                                    self.menu.state = MenuState::Main;
+                                    self.scene = AppScene::Playing;
+                                    self.new_game(ctx)?;
+                                }
+                            }
+                            Some(Action::Back) => self.menu.state = MenuState::Main,
+                            _ => {}
+                        }
+                    }
+                    MenuState::Opponent => {
+                        let opponent = &mut self.menu.opponent;
+                        match action {
+                            Some(Action::MoveUp) => opponent.move_selection(true),
+                            Some(Action::MoveDown) => opponent.move_selection(false),
+                            Some(Action::Confirm) => {
+                                self.opponent = opponent.selected_kind();
+                                self.mode = GameMode::Versus;
+                                self.menu.state = MenuState::Main;
+                                self.scene = AppScene::Playing;
+                                self.new_game(ctx)?;
+                            }
+                            Some(Action::Back) => self.menu.state = MenuState::Main,
+                            _ => {}
+                        }
+                    }
+                    MenuState::Scores => {
+                        match action {
+                            Some(Action::MoveLeft) => self.menu.move_scores_page(true),
+                            Some(Action::MoveRight) => self.menu.move_scores_page(false),
+                            Some(Action::Back | Action::Confirm) => self.menu.state = MenuState::Main,
+                            _ => {}
+                        }
+                    }
+                    MenuState::Controls => {
+                        let (controls, keys) = (&mut self.menu.controls, &mut self.menu.keys);
+                        let mut changed = false;
+                        if controls.capturing {
+                            // Any key but Back is taken as is, even one that
+                            // means something in the menus. Held keys repeat,
+                            // and the Confirm press that started capturing
+                            // must not bind itself.
+                            if repeated {
+                                return Ok(());
+                            }
+                            controls.capturing = false;
+                            if action == Some(Action::Back) {
+                                controls.message = None;
+                            } else if let Some(selected) = controls.selected_action() {
+                                match keys.bind(selected, key) {
+                                    Ok(()) => {
+                                        controls.message = None;
+                                        changed = true;
+                                    }
+                                    Err(problem) => controls.message = Some(problem),
+                                }
+                            }
+                        } else {
+                            match (action, controls.selected_action()) {
+                                // Backspace always unbinds here, whatever it
+                                // is bound to, one key per press.
+                                (_, Some(_)) if key == KeyCode::Back && repeated => {}
+                                (_, Some(selected)) if key == KeyCode::Back => {
+                                    changed = keys.unbind_last(selected);
+                                    if !changed {
+                                        controls.message = Some(format!("{} needs at least one key", selected.label()));
                                     }
                                 }
+                                (Some(Action::MoveUp), _) => controls.move_selection(true),
+                                (Some(Action::MoveDown), _) => controls.move_selection(false),
+                                (Some(Action::Confirm), Some(_)) => {
+                                    controls.capturing = true;
+                                    controls.message = None;
+                                }
+                                (Some(Action::Confirm), None) => {
+                                    keys.reset();
+                                    controls.message = Some("Every key is back to its default".to_string());
+                                    changed = true;
+                                }
+                                (Some(Action::Back), _) => self.menu.state = MenuState::Main,
                                 _ => {}
                             }
                         }
+                        if changed && let Err(err) = self.menu.keys.save() {
+                            eprintln!("cannot save controls: {}", err);
+                        }
                     }
-                    MenuState::Level => {}
                 }
                 
             }
             AppScene::GameOver => {
-                if input.keycode == Some(KeyCode::R) {
-                    self.scene = AppScene::Menu;
+                if let Some(name) = &mut self.name_entry {
+                    // Typing a name uses the keys as labelled, not the
+                    // key map.
+                    match key {
+                        KeyCode::Back => {
+                            name.pop();
+                        }
+                        KeyCode::Return | KeyCode::NumpadEnter => {
+                            let name = self.name_entry.take().unwrap_or_default();
+                            let name = name.trim();
+                            self.record_score(if name.is_empty() { "Anonymous" } else { name }.to_string());
+                        }
+                        _ => {}
+                    }
+                } else {
+                    let game_over = &mut self.menu.game_over;
+                    match action {
+                        Some(Action::MoveLeft | Action::MoveUp) => game_over.move_selection(true),
+                        Some(Action::MoveRight | Action::MoveDown) => game_over.move_selection(false),
+                        Some(Action::Restart) => {
+                            self.scene = AppScene::Playing;
+                            self.new_game(ctx)?;
+                        }
+                        Some(Action::Confirm) => match game_over.selected_option() {
+                            "Retry" => {
+                                self.scene = AppScene::Playing;
+                                self.new_game(ctx)?;
+                            }
+                            "Watch Replay" => {
+                                if let Some(game) = &self.game {
+                                    self.watch_replay(ctx, game.replay())?;
+                                }
+                            }
+                            "Change Level" => self.show_menu(ctx, MenuState::Level)?,
+                            _ => self.show_menu(ctx, MenuState::Main)?,
+                        },
+                        _ => {}
+                    }
                 }
             }
             AppScene::Pause => {
-
+                let pause = &mut self.menu.pause;
+                match action {
+                    Some(Action::MoveUp) => pause.move_selection(true),
+                    Some(Action::MoveDown) => pause.move_selection(false),
+                    Some(Action::Back) => self.resume(),
+                    Some(Action::Confirm) => match pause.selected_option() {
+                        "Continue" => self.resume(),
+                        "Restart" => {
+                            self.resume();
+                            self.audio.replay_bgm(ctx);
+                            self.new_game(ctx)?;
+                        }
+                        "Back to menu" => {
+                            self.audio.resume_bgm();
+                            self.show_menu(ctx, MenuState::Main)?;
+                        }
+                        _ => {
+                            self.audio.stop_bgm(ctx);
+                            ctx.request_quit();
+                        }
+                    },
+                    _ => {}
+                }
+            }
+            AppScene::Replay => {
+                if action == Some(Action::Back) {
+                    self.replay = None;
+                    // Replays opened from the command line have no game to
+                    // go back to.
+                    if self.game.as_ref().is_some_and(|game| game.is_over()) {
+                        self.scene = AppScene::GameOver;
+                    } else {
+                        self.show_menu(ctx, MenuState::Main)?;
+                    }
+                } else if let Some(replay) = &mut self.replay {
+                    replay.key_down_event(input, &self.menu.keys);
+                }
+            }
+            AppScene::Network => {
+                if action == Some(Action::Back) {
+                    // Dropping the session closes the connections.
+                    self.net = None;
+                    self.show_menu(ctx, MenuState::Main)?;
+                } else if let Some(net) = &mut self.net {
+                    net.key_down_event(key, &self.menu.keys);
+                }
+            }
+            AppScene::Watch => {
+                if action == Some(Action::Back) {
+                    self.watch = None;
+                    self.show_menu(ctx, MenuState::Main)?;
+                }
+            }
+            AppScene::Editor => {
+                if let Some(editor) = &mut self.editor {
+                    match editor.key_down_event(input, &self.levels_dir) {
+                        EditorAction::None => {}
+                        EditorAction::Playtest => {
+                            let level = editor.level.clone();
+                            self.playtest = true;
+                            self.scene = AppScene::Playing;
+                            self.start_game(ctx, level, GameMode::Classic)?;
+                        }
+                        EditorAction::Saved => {
+                            self.menu.level = LevelSelect::from_dir(&self.levels_dir);
+                        }
+                        EditorAction::Resized => editor.view.resize_window(ctx)?,
+                        EditorAction::Exit => self.show_menu(ctx, MenuState::Main)?,
+                    }
+                }
             }
             AppScene::Playing => {
-                if input.keycode == Some(KeyCode::Escape) {
+                if self.menu.keys.is(key, Action::Pause) {
+                    if self.playtest {
+                        self.playtest = false;
+                        self.scene = AppScene::Editor;
+                        return Ok(());
+                    }
+                    self.menu.pause.selected = 0;
+                    self.audio.pause_bgm();
                     self.scene = AppScene::Pause;
+                    return Ok(());
                 }
                 if let Some(game) = &mut self.game {
-                    game.key_down_event(ctx, input, false)?;
+                    game.key_down_event(key, &self.menu.keys);
                 }
             }
         }
         Ok(())
     }
+
+    fn mouse_button_down_event(
+            &mut self,
+            _ctx: &mut Context,
+            button: MouseButton,
+            x: f32,
+            y: f32,
+        ) -> Result<(), GameError> {
+        if let (AppScene::Editor, Some(editor)) = (self.scene, &mut self.editor) {
+            editor.mouse_button_down_event(button, x, y);
+        }
+        Ok(())
+    }
+
+    fn mouse_button_up_event(
+            &mut self,
+            _ctx: &mut Context,
+            _button: MouseButton,
+            _x: f32,
+            _y: f32,
+        ) -> Result<(), GameError> {
+        if let (AppScene::Editor, Some(editor)) = (self.scene, &mut self.editor) {
+            editor.mouse_button_up_event();
+        }
+        Ok(())
+    }
+
+    fn mouse_motion_event(
+            &mut self,
+            _ctx: &mut Context,
+            x: f32,
+            y: f32,
+            _dx: f32,
+            _dy: f32,
+        ) -> Result<(), GameError> {
+        if let (AppScene::Editor, Some(editor)) = (self.scene, &mut self.editor) {
+            editor.mouse_motion_event(x, y);
+        }
+        Ok(())
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> Result<(), GameError> {
+        match (self.scene, &mut self.editor, &mut self.name_entry) {
+            (AppScene::Editor, Some(editor), _) => editor.text_input_event(character),
+            (AppScene::GameOver, _, Some(name))
+                if !character.is_control() && name.chars().count() < NAME_LEN =>
+            {
+                name.push(character);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Settings given on the command line that apply to every game.
+#[derive(Debug, Clone, Default)]
+struct Options {
+    seed: Option<u64>,
+    /// Replaces each level's own wrap mode when set.
+    wrap: Option<WrapMode>,
+    /// How many turns each snake keeps for the coming ticks; defaults to
+    /// [`sim::TURN_BUFFER`].
+    turn_buffer: Option<usize>,
+    /// A replay file to watch right away.
+    replay: Option<path::PathBuf>,
+    /// Whether to host a networked game on the first level.
+    host: bool,
+    /// The port to host on; defaults to [`net::DEFAULT_PORT`].
+    port: Option<u16>,
+    /// The address of a networked game to join.
+    join: Option<String>,
+    /// The port to let spectators watch on, if any.
+    spectators: Option<u16>,
+    /// The address of a broadcast to watch.
+    watch: Option<String>,
+}
+
+/// Reads `--seed <n>`, `--wrap <mode>`, `--turn-buffer <n>`,
+/// `--replay <file>`, `--host`, `--port <n>`, `--join <addr>`,
+/// `--spectators <port>` and `--watch <addr>` (or `--flag=value`) from the
+/// command line.
+fn parse_args() -> GameResult<Options> {
+    let mut options = Options::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || value.clone().or_else(|| args.next());
+        match flag.as_str() {
+            "--seed" => match value().as_deref().map(str::parse::<u64>) {
+                Some(Ok(seed)) => options.seed = Some(seed),
+                _ => return Err(GameError::CustomError(
+                    "--seed expects an unsigned integer, e.g. --seed 42".to_string()
+                )),
+            },
+            "--wrap" => match value().as_deref().and_then(WrapMode::parse) {
+                Some(wrap) => options.wrap = Some(wrap),
+                None => return Err(GameError::CustomError(
+                    "--wrap expects one of both, off, horizontal, vertical".to_string()
+                )),
+            },
+            "--turn-buffer" => match value().as_deref().map(str::parse::<usize>) {
+                Some(Ok(depth)) if depth > 0 => options.turn_buffer = Some(depth),
+                _ => return Err(GameError::CustomError(
+                    "--turn-buffer expects a positive number of turns, e.g. --turn-buffer 3".to_string()
+                )),
+            },
+            "--replay" => match value() {
+                Some(file) => options.replay = Some(path::PathBuf::from(file)),
+                None => return Err(GameError::CustomError(
+                    "--replay expects a replay file".to_string()
+                )),
+            },
+            "--host" => options.host = true,
+            "--port" => match value().as_deref().map(str::parse::<u16>) {
+                Some(Ok(port)) => options.port = Some(port),
+                _ => return Err(GameError::CustomError(
+                    "--port expects a port number, e.g. --port 7878".to_string()
+                )),
+            },
+            "--join" => match value() {
+                Some(addr) => options.join = Some(addr),
+                None => return Err(GameError::CustomError(
+                    "--join expects an address, e.g. --join 127.0.0.1:7878".to_string()
+                )),
+            },
+            "--spectators" => match value().as_deref().map(str::parse::<u16>) {
+                Some(Ok(port)) => options.spectators = Some(port),
+                _ => return Err(GameError::CustomError(
+                    "--spectators expects a port number, e.g. --spectators 7879".to_string()
+                )),
+            },
+            "--watch" => match value() {
+                Some(addr) => options.watch = Some(addr),
+                None => return Err(GameError::CustomError(
+                    "--watch expects an address, e.g. --watch 127.0.0.1:7879".to_string()
+                )),
+            },
+            _ => {}
+        }
+    }
+    Ok(options)
 }
 
 fn main() -> GameResult {
+    let options = parse_args()?;
+    let online = (options.host || options.join.is_some()).then(|| {
+        (options.port.unwrap_or(net::DEFAULT_PORT), options.join.clone())
+    });
+    let (spectators, watch) = (options.spectators, options.watch.clone());
+    let replay = options.replay.as_deref().map(Replay::load).transpose().map_err(GameError::CustomError)?;
+
     let resource_dir = if let Ok(maiifest_dir) = env::var("CARGO_MANIFEST_DIR") {
         let mut path = path::PathBuf::from(maiifest_dir);
         path.push("assert");
-        path.push("audio");
+        path.push("autio");
         println!("{:?}", path);
         path
     } else {
         println!("re");
         path::PathBuf::from("./resources")
     };
+
+    let levels_dir = match env::var("CARGO_MANIFEST_DIR") {
+        Ok(manifest_dir) => path::PathBuf::from(manifest_dir).join("assert").join("levels"),
+        Err(_) => path::PathBuf::from("./resources/levels"),
+    };
     
     let cb = ggez::ContextBuilder::new("snake", "Gray Olson")
         
@@ -185,7 +940,19 @@ fn main() -> GameResult {
 
     let (mut ctx, events_loop) = cb.build()?;
 
-    let state = AppState::new(&mut ctx);
+    let mut state = AppState::new(&mut ctx, &levels_dir, options);
+    if let Some(replay) = replay {
+        state.watch_replay(&mut ctx, replay)?;
+    }
+    if let Some((port, join)) = online {
+        state.go_online(port, join.as_deref())?;
+    }
+    if let Some(port) = spectators {
+        state.open_broadcast(port)?;
+    }
+    if let Some(addr) = watch {
+        state.watch_game(&addr)?;
+    }
     
     event::run(ctx, events_loop, state)
 }
\ No newline at end of file
@@ -1,13 +1,25 @@
-use std::vec;
+use std::{path::Path, vec};
 
 use ggez::{ event, glam, graphics::{self, Drawable, TextFragment}, Context, GameError, GameResult };
-use crate::game;
-use crate::level::LevelSelect;
+use crate::controller::ControllerKind;
+use crate::controls::{Action, Controls};
+use crate::game::{format_time, GameMode};
+use crate::level::{Level, LevelError};
+use crate::scores::HighScores;
 
 #[derive(Debug, Clone)]
 pub struct MenuManager {
     pub main: MainMenu,
     pub level: LevelSelect,
+    pub scores: HighScores,
+    /// The key map, edited on the controls screen.
+    pub keys: Controls,
+    pub controls: ControlsMenu,
+    pub opponent: OpponentMenu,
+    pub pause: PauseMenu,
+    pub game_over: GameOverMenu,
+    /// Which level's tables the high score screen shows.
+    pub scores_page: usize,
     pub state: MenuState,
 }
 
@@ -15,26 +27,104 @@ pub struct MenuManager {
 pub enum MenuState {
     Main,
     Level,
+    Scores,
+    Opponent,
+    Controls,
 }
 
 impl MenuManager {
-    pub fn new() -> Self{
+    pub fn new(level: LevelSelect, scores: HighScores, keys: Controls) -> Self{
         Self {
             main: MainMenu::new(),
-            level: LevelSelect::new(),
+            level,
+            scores,
+            keys,
+            controls: ControlsMenu::new(),
+            opponent: OpponentMenu::new(),
+            pause: PauseMenu::new(),
+            game_over: GameOverMenu::new(),
+            scores_page: 0,
             state: MenuState::Main,
         }
     }
+
+    pub fn move_scores_page(&mut self, left: bool) {
+        let pages = self.level.levels.len().max(1);
+        if left {
+            self.scores_page = (self.scores_page + pages - 1) % pages;
+        } else {
+            self.scores_page = (self.scores_page + 1) % pages;
+        }
+    }
+
+    fn draw_scores(&self, ctx: &mut Context) -> GameResult {
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from([0.0, 1.0, 0.0, 1.0]));
+        let (width, _) = ctx.gfx.drawable_size();
+
+        let level = self.level.levels.get(self.scores_page).map_or("", |l| l.name.as_str());
+        let title = graphics::Text::new(
+            TextFragment::new(format!("< High Scores: {} >", level)).scale(40.0)
+        );
+        let rect = title.dimensions(ctx).unwrap();
+        let pos = glam::Vec2::new(width / 2.0 - rect.w / 2.0, 30.0);
+        canvas.draw(&title, graphics::DrawParam::new().dest(pos).color([1.0, 1.0, 1.0, 1.0]));
+
+        let mut y = 100.0;
+        for mode in GameMode::ALL.into_iter().filter(|mode| mode.keeps_scores()) {
+            let header = graphics::Text::new(
+                TextFragment::new(mode.name().to_string()).scale(28.0)
+            );
+            canvas.draw(&header, graphics::DrawParam::new().dest(glam::Vec2::new(60.0, y)).color([1.0, 1.0, 0.0, 1.0]));
+            y += 34.0;
+
+            let table = self.scores.table(level, mode.name());
+            if table.is_empty() {
+                let text = graphics::Text::new(TextFragment::new("No runs yet".to_string()).scale(22.0));
+                canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(80.0, y)).color([1.0, 1.0, 1.0, 1.0]));
+                y += 28.0;
+            }
+            for (rank, entry) in table.iter().enumerate() {
+                let text = graphics::Text::new(TextFragment::new(format!(
+                    "{:>2}. {:<16} {:>5}   length {:>3}   {:>6}   seed {}   {}",
+                    rank + 1,
+                    entry.name,
+                    entry.score,
+                    entry.length,
+                    format_time(entry.secs),
+                    entry.seed,
+                    entry.date,
+                )).scale(22.0));
+                canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(80.0, y)).color([1.0, 1.0, 1.0, 1.0]));
+                y += 28.0;
+            }
+            y += 10.0;
+        }
+
+        canvas.finish(ctx)
+    }
 }
 
 impl event::EventHandler<GameError> for MenuManager {
     fn draw(&mut self, ctx: &mut Context) -> Result<(), GameError> {
         match self.state {
             MenuState::Main => {
-                self.main.draw(ctx)
+                let mut canvas =
+                    graphics::Canvas::from_frame(ctx, graphics::Color::from([0.0, 1.0, 0.0, 1.0]));
+                self.main.draw(ctx, &mut canvas);
+                canvas.finish(ctx)
             }
             MenuState::Level => {
-                Ok(())
+                self.level.draw(ctx)
+            }
+            MenuState::Scores => {
+                self.draw_scores(ctx)
+            }
+            MenuState::Opponent => {
+                self.opponent.draw(ctx)
+            }
+            MenuState::Controls => {
+                self.controls.draw(ctx, &self.keys)
             }
         }
     }
@@ -54,10 +144,14 @@ impl MainMenu {
     pub fn new() -> Self {
         MainMenu {
             selected: 0,
-            options: vec!["Start Game", "Exit"],
+            options: vec!["Start Game", "Versus", "Select Level", "High Scores", "Level Editor", "Controls", "Exit"],
         }
     }
 
+    pub fn selected_option(&self) -> &'static str {
+        self.options[self.selected]
+    }
+
     pub fn move_selection(&mut self, up: bool) {
         if up {
             self.selected = (self.selected + self.options.len() - 1) % self.options.len();
@@ -65,16 +159,286 @@ impl MainMenu {
             self.selected = (self.selected + 1) % self.options.len();
         }
     }
+
+    /// Dims whatever is already on `canvas`, usually the demo game, and
+    /// draws the options on top.
+    pub fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) {
+        dim(ctx, canvas);
+
+        let mut y = 200.0;
+        let font_size = 50.0;
+        for (i, option) in self.options.iter().enumerate() {
+            let color = if i == self.selected {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+            let text = graphics::Text::new(
+                TextFragment::new((*option).to_string()).scale(font_size)
+            );
+            let rect = text.dimensions(ctx).unwrap();
+            let pos = glam::Vec2::new(ctx.gfx.drawable_size().0 / 2.0 - rect.w / 2.0, y);
+            canvas.draw(&text, graphics::DrawParam::new().dest(pos).color(color));
+            y += 50.0;
+        }
+    }
 }
 
-impl event::EventHandler<GameError> for MainMenu {
+/// Picks who plays the second snake in a versus game: a human on the
+/// second player's keys or one of the AIs.
+#[derive(Debug, Clone)]
+pub struct OpponentMenu {
+    pub selected: usize,
+}
+
+impl OpponentMenu {
+    pub fn new() -> Self {
+        OpponentMenu { selected: 0 }
+    }
+
+    pub fn selected_kind(&self) -> ControllerKind {
+        ControllerKind::ALL[self.selected]
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        let len = ControllerKind::ALL.len();
+        if up {
+            self.selected = (self.selected + len - 1) % len;
+        } else {
+            self.selected = (self.selected + 1) % len;
+        }
+    }
+}
+
+impl event::EventHandler<GameError> for OpponentMenu {
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from([0.0, 1.0, 0.0, 1.0]));
+        let width = ctx.gfx.drawable_size().0;
+
+        let title = graphics::Text::new(TextFragment::new("Player 2".to_string()).scale(60.0));
+        let rect = title.dimensions(ctx).unwrap();
+        canvas.draw(&title, graphics::DrawParam::new().dest(glam::Vec2::new(width / 2.0 - rect.w / 2.0, 100.0)));
+
         let mut y = 200.0;
+        for (i, kind) in ControllerKind::ALL.iter().enumerate() {
+            let color = if i == self.selected {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+            let text = graphics::Text::new(TextFragment::new(kind.label().to_string()).scale(50.0));
+            let rect = text.dimensions(ctx).unwrap();
+            let pos = glam::Vec2::new(width / 2.0 - rect.w / 2.0, y);
+            canvas.draw(&text, graphics::DrawParam::new().dest(pos).color(color));
+            y += 50.0;
+        }
+
+        canvas.finish(ctx)?;
+        Ok(())
+    }
 
+    fn update(&mut self, _ctx: &mut Context) -> Result<(), GameError> {
+        Ok(())
+    }
+}
+
+/// Lists every action with its keys. Picking one waits for a key to add to
+/// it; the last row puts every key back to its default.
+#[derive(Debug, Clone)]
+pub struct ControlsMenu {
+    pub selected: usize,
+    /// Set while waiting for the key to bind to the selected action.
+    pub capturing: bool,
+    /// Why the last key could not be bound, or what was changed.
+    pub message: Option<String>,
+}
+
+impl ControlsMenu {
+    pub fn new() -> Self {
+        ControlsMenu { selected: 0, capturing: false, message: None }
+    }
+
+    /// The selected action, or `None` on the reset row.
+    pub fn selected_action(&self) -> Option<Action> {
+        Action::ALL.get(self.selected).copied()
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        let len = Action::ALL.len() + 1;
+        if up {
+            self.selected = (self.selected + len - 1) % len;
+        } else {
+            self.selected = (self.selected + 1) % len;
+        }
+    }
+
+    pub fn draw(&self, ctx: &mut Context, keys: &Controls) -> GameResult {
         let mut canvas =
             graphics::Canvas::from_frame(ctx, graphics::Color::from([0.0, 1.0, 0.0, 1.0]));
-        let font_size = 50.0;
+        let (width, height) = ctx.gfx.drawable_size();
+
+        let title = graphics::Text::new(TextFragment::new("Controls".to_string()).scale(40.0));
+        let rect = title.dimensions(ctx).unwrap();
+        canvas.draw(&title, graphics::DrawParam::new().dest(glam::Vec2::new(width / 2.0 - rect.w / 2.0, 20.0)));
+
+        let mut y = 80.0;
+        for i in 0..=Action::ALL.len() {
+            let color = if i == self.selected {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+            let line = match Action::ALL.get(i) {
+                Some(&action) if self.capturing && i == self.selected => {
+                    format!("{:<16} press a key, {} to cancel", action.label(), keys.hint(Action::Back))
+                }
+                Some(&action) => format!("{:<16} {}", action.label(), keys.describe(action)),
+                None => "Reset to defaults".to_string(),
+            };
+            let text = graphics::Text::new(TextFragment::new(line).scale(26.0));
+            canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(80.0, y)).color(color));
+            y += 31.0;
+        }
+
+        if let Some(message) = &self.message {
+            let text = graphics::Text::new(TextFragment::new(message.clone()).scale(24.0));
+            canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(80.0, height - 75.0)).color([1.0, 1.0, 1.0, 1.0]));
+        }
+        let help = graphics::Text::new(
+            TextFragment::new(format!(
+                "{}: add a key   Backspace: remove the last one   {}: back",
+                keys.hint(Action::Confirm),
+                keys.hint(Action::Back),
+            ))
+            .scale(22.0),
+        );
+        canvas.draw(&help, graphics::DrawParam::new().dest(glam::Vec2::new(80.0, height - 40.0)));
+
+        canvas.finish(ctx)
+    }
+}
+
+/// Darkens everything drawn on `canvas` so far, for text shown over a
+/// frozen board.
+pub fn dim(ctx: &Context, canvas: &mut graphics::Canvas) {
+    let (width, height) = ctx.gfx.drawable_size();
+    canvas.draw(
+        &graphics::Quad,
+        graphics::DrawParam::new()
+            .dest_rect(graphics::Rect::new(0.0, 0.0, width, height))
+            .color([0.0, 0.0, 0.0, 0.6]),
+    );
+}
+
+/// The options under the statistics of a finished game.
+#[derive(Debug, Clone)]
+pub struct GameOverMenu {
+    pub selected: usize,
+    pub options: Vec<&'static str>,
+    /// Whether the finished game can be watched again; "Watch Replay" is
+    /// skipped while it is not.
+    pub replay_available: bool,
+}
+
+impl GameOverMenu {
+    pub fn new() -> Self {
+        GameOverMenu {
+            selected: 0,
+            options: vec!["Retry", "Watch Replay", "Change Level", "Main Menu"],
+            replay_available: false,
+        }
+    }
+
+    fn enabled(&self, i: usize) -> bool {
+        self.options[i] != "Watch Replay" || self.replay_available
+    }
+
+    pub fn selected_option(&self) -> &'static str {
+        self.options[self.selected]
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        let len = self.options.len();
+        loop {
+            if up {
+                self.selected = (self.selected + len - 1) % len;
+            } else {
+                self.selected = (self.selected + 1) % len;
+            }
+            if self.enabled(self.selected) {
+                break;
+            }
+        }
+    }
+
+    /// Draws the options in a row at height `y`.
+    pub fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas, y: f32) {
+        let texts: Vec<_> = self
+            .options
+            .iter()
+            .map(|option| graphics::Text::new(TextFragment::new((*option).to_string()).scale(32.0)))
+            .collect();
+        let gap = 40.0;
+        let widths: Vec<f32> = texts.iter().map(|text| text.dimensions(ctx).unwrap().w).collect();
+        let total = widths.iter().sum::<f32>() + gap * (texts.len() - 1) as f32;
+        let mut x = ctx.gfx.drawable_size().0 / 2.0 - total / 2.0;
+        for (i, (text, w)) in texts.iter().zip(widths).enumerate() {
+            let color = if !self.enabled(i) {
+                [0.5, 0.5, 0.5, 1.0]
+            } else if i == self.selected {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+            canvas.draw(text, graphics::DrawParam::new().dest(glam::Vec2::new(x, y)).color(color));
+            x += w + gap;
+        }
+    }
+}
+
+/// The menu shown over a paused game.
+#[derive(Debug, Clone)]
+pub struct PauseMenu {
+    pub selected: usize,
+    pub options: Vec<&'static str>
+}
+
+impl PauseMenu {
+    pub fn new() -> Self {
+        PauseMenu {
+            selected: 0,
+            options: vec!["Continue", "Restart", "Back to menu", "Quit"],
+        }
+    }
+
+    pub fn selected_option(&self) -> &'static str {
+        self.options[self.selected]
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        if up {
+            self.selected = (self.selected + self.options.len() - 1) % self.options.len();
+        } else {
+            self.selected = (self.selected + 1) % self.options.len();
+        }
+    }
+
+    /// Dims whatever is already on `canvas` and draws the options on top.
+    pub fn draw(&self, ctx: &mut Context, canvas: &mut graphics::Canvas) {
+        let (width, height) = ctx.gfx.drawable_size();
+        dim(ctx, canvas);
+
+        let title = graphics::Text::new(
+            TextFragment::new("Paused".to_string()).scale(55.0)
+        );
+        let rect = title.dimensions(ctx).unwrap();
+        let mut y = height / 2.0 - 150.0;
+        let pos = glam::Vec2::new(width / 2.0 - rect.w / 2.0, y);
+        canvas.draw(&title, graphics::DrawParam::new().dest(pos).color([1.0, 1.0, 1.0, 1.0]));
+        y += 80.0;
+
         for (i, option) in self.options.iter().enumerate() {
             let color = if i == self.selected {
                 [1.0, 1.0, 0.0, 1.0]
@@ -82,12 +446,136 @@ impl event::EventHandler<GameError> for MainMenu {
                 [1.0, 1.0, 1.0, 1.0]
             };
             let text = graphics::Text::new(
-                TextFragment::new((*option).to_string()).scale(font_size)
+                TextFragment::new((*option).to_string()).scale(45.0)
             );
             let rect = text.dimensions(ctx).unwrap();
-            let pos = glam::Vec2::new(game::SCREEN_SIZE.0 / 2.0 - rect.w / 2.0, y);
+            let pos = glam::Vec2::new(width / 2.0 - rect.w / 2.0, y);
             canvas.draw(&text, graphics::DrawParam::new().dest(pos).color(color));
-            y += 50.0;
+            y += 55.0;
+        }
+    }
+}
+
+/// Size in pixels of the level preview thumbnails; boards of any size are
+/// scaled to fit.
+const THUMB_SIZE: (f32, f32) = (120.0, 80.0);
+
+fn draw_thumbnail(level: &Level, canvas: &mut graphics::Canvas, origin: glam::Vec2) {
+    let cell = (THUMB_SIZE.0 / level.size.0 as f32).min(THUMB_SIZE.1 / level.size.1 as f32);
+    let board = graphics::Rect::new(
+        origin.x,
+        origin.y,
+        level.size.0 as f32 * cell,
+        level.size.1 as f32 * cell,
+    );
+    canvas.draw(
+        &graphics::Quad,
+        graphics::DrawParam::new()
+            .dest_rect(board)
+            .color(graphics::Color::from_rgb(15, 15, 28)),
+    );
+    for pos in &level.obstacles {
+        let rect = graphics::Rect::new(
+            origin.x + pos.x as f32 * cell,
+            origin.y + pos.y as f32 * cell,
+            cell,
+            cell,
+        );
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(rect)
+                .color(graphics::Color::from_rgb(120, 120, 130)),
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LevelSelect {
+    pub selected: usize,
+    pub levels: Vec<Level>,
+    /// Problems found while loading level files, shown on the select screen.
+    pub errors: Vec<LevelError>,
+}
+
+impl LevelSelect {
+    /// Loads every level file in `dir`, falling back to the built-in levels
+    /// when none could be loaded.
+    pub fn from_dir(dir: &Path) -> Self {
+        let (levels, errors) = Level::load_dir(dir);
+        for err in &errors {
+            eprintln!("{}", err);
+        }
+        let levels = if levels.is_empty() { Level::basic_levels() } else { levels };
+        Self { selected: 0, levels, errors }
+    }
+
+    pub fn move_selection(&mut self, up: bool) {
+        if self.levels.is_empty() {
+            return;
+        }
+        if up {
+            self.selected = (self.selected + self.levels.len() - 1) % self.levels.len();
+        } else {
+            self.selected = (self.selected + 1) % self.levels.len();
+        }
+    }
+
+    pub fn selected_level(&self) -> Option<&Level> {
+        self.levels.get(self.selected)
+    }
+}
+
+impl event::EventHandler<GameError> for LevelSelect {
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from([0.0, 1.0, 0.0, 1.0]));
+
+        let title = graphics::Text::new(
+            TextFragment::new("Select Level".to_string()).scale(50.0)
+        );
+        let (width, height) = ctx.gfx.drawable_size();
+        let rect = title.dimensions(ctx).unwrap();
+        let pos = glam::Vec2::new(width / 2.0 - rect.w / 2.0, 30.0);
+        canvas.draw(&title, graphics::DrawParam::new().dest(pos).color([1.0, 1.0, 1.0, 1.0]));
+
+        // Scroll so the selected level always stays on screen.
+        let row_height = THUMB_SIZE.1 + 20.0;
+        let visible = (((height - 200.0) / row_height) as usize).max(1);
+        let first = self.selected.saturating_sub(visible - 1);
+        let x = width / 2.0 - 300.0;
+        let mut y = 110.0;
+        for (i, level) in self.levels.iter().enumerate().skip(first).take(visible) {
+            let color = if i == self.selected {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+            draw_thumbnail(level, &mut canvas, glam::Vec2::new(x, y));
+
+            let text_x = x + THUMB_SIZE.0 + 30.0;
+            let name = graphics::Text::new(
+                TextFragment::new(level.name.clone()).scale(40.0)
+            );
+            canvas.draw(&name, graphics::DrawParam::new().dest(glam::Vec2::new(text_x, y)).color(color));
+            let speed = graphics::Text::new(
+                TextFragment::new(format!(
+                    "Speed: {}   Size: {}x{}   Wrap: {}",
+                    level.speed, level.size.0, level.size.1, level.wrap.name()
+                )).scale(25.0)
+            );
+            canvas.draw(&speed, graphics::DrawParam::new().dest(glam::Vec2::new(text_x, y + 45.0)).color(color));
+            y += row_height;
+        }
+
+        let mut y = height - 30.0 * self.errors.len().min(3) as f32 - 10.0;
+        for err in self.errors.iter().take(3) {
+            let text = graphics::Text::new(
+                TextFragment::new(err.to_string()).scale(20.0)
+            );
+            canvas.draw(&text, graphics::DrawParam::new().dest(glam::Vec2::new(20.0, y)).color([1.0, 0.0, 0.0, 1.0]));
+            y += 30.0;
         }
 
         canvas.finish(ctx)?;
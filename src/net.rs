@@ -0,0 +1,939 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::controller::{KeyboardController, SnakeController};
+use crate::level::Level;
+use crate::sim::{Direction, Simulation, TickOutcome, TURN_BUFFER};
+
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// Ticks between a turn being made and being played, giving it time to
+/// reach every machine before it is needed.
+pub const INPUT_DELAY: u32 = 3;
+
+pub const MAX_PLAYERS: usize = 4;
+
+/// Sent by clients when they connect; the host turns away other versions.
+const PROTOCOL: &str = "snake-net 1";
+
+/// How many ticks back the host remembers its checksums for comparing
+/// with the clients'.
+const CHECKSUM_HISTORY: u32 = 512;
+
+/// Messages waiting to be written to one connection. A peer that lets this
+/// many pile up has stopped reading and is dropped.
+const OUTBOX: usize = 256;
+
+/// How long writing to a peer may block before it counts as gone.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest game spectators can still catch up on when they join late, in
+/// ticks. Later they wait for the next game.
+const CATCH_UP_TICKS: usize = 30_000;
+
+/// How long a new connection has to say hello before the host hangs up.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the host waits for a player's turn before going on without
+/// that player.
+const TURN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A TCP connection carrying one message per line. Lines are read and
+/// written on threads of their own, so neither polling nor sending ever
+/// blocks the game.
+struct Connection {
+    stream: TcpStream,
+    lines: Receiver<String>,
+    outbox: SyncSender<String>,
+    open: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut writer = stream.try_clone()?;
+        let (outbox, unsent) = mpsc::sync_channel::<String>(OUTBOX);
+        thread::spawn(move || {
+            // Runs until the connection is dropped, after writing what was
+            // left to send, or until the peer stops reading.
+            for text in unsent {
+                if writer.write_all(text.as_bytes()).is_err() {
+                    break;
+                }
+            }
+            // The reader thread holds a clone of the stream, which would
+            // keep the connection open.
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+        Ok(Connection { stream, lines, outbox, open: true })
+    }
+
+    fn send(&mut self, line: &str) {
+        self.queue(format!("{}\n", line));
+    }
+
+    /// Sends many lines at once, taking a single place in the outbox.
+    fn send_all<'a>(&mut self, lines: impl IntoIterator<Item = &'a String>) {
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        if !text.is_empty() {
+            self.queue(text);
+        }
+    }
+
+    fn queue(&mut self, text: String) {
+        if !self.open {
+            return;
+        }
+        match self.outbox.try_send(text) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.close(),
+            Err(TrySendError::Disconnected(_)) => self.open = false,
+        }
+    }
+
+    /// Hangs up at once, without sending what is still queued.
+    fn close(&mut self) {
+        self.open = false;
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    /// Every line that arrived since the last call.
+    fn receive(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            match self.lines.try_recv() {
+                Ok(line) => lines.push(line),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.open = false;
+                    break;
+                }
+            }
+        }
+        lines
+    }
+}
+
+fn turn_letter(turn: Option<Direction>) -> char {
+    turn.map_or('-', Direction::letter)
+}
+
+fn parse_turn(s: &str) -> Option<Option<Direction>> {
+    match s {
+        "-" => Some(None),
+        _ => Some(Some(Direction::from_letter(s.chars().next()?)?)),
+    }
+}
+
+/// Puts a level file on one line.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some(c) => text.push(c),
+            None => {}
+        }
+    }
+    text
+}
+
+/// Where a networked game is at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// Waiting for players to join and the host to start.
+    Lobby,
+    Running,
+    Over,
+    /// The machines disagreed about the game after this tick.
+    Desync(u32),
+    Disconnected(String),
+}
+
+/// One machine's side of a networked game for two to four players.
+///
+/// The game runs in lockstep: every machine plays the same
+/// [`Simulation`], and a tick is only played once the turns of every
+/// player for it are known. The host collects the turns, plays each tick
+/// first and sends it out as a frame with everybody's turns; clients play
+/// the frames as they come. Turns are made [`INPUT_DELAY`] ticks ahead, so
+/// they usually arrive before they are needed and nobody waits.
+///
+/// After each tick the clients send a checksum of their game, and the host
+/// stops the game if one differs from its own.
+///
+/// Messages are lines of text:
+///
+/// ```text
+/// client: hello snake-net 1         host: welcome <player>
+///         input <tick> <U|D|L|R|->        lobby <players>
+///         check <tick> <checksum>         start <seed> <players> <delay> <level>
+///                                         frame <tick> <turn per player>
+///                                         desync <tick>
+///                                         bye <reason>
+/// ```
+///
+/// Connections that do not say hello within a few seconds are dropped,
+/// and so are players who stop reading or, once the game runs, stop
+/// sending their turns for too long.
+pub struct Session {
+    host: bool,
+    /// Accepts new players while the host is in the lobby.
+    listener: Option<TcpListener>,
+    port: u16,
+    /// On the host, one connection per client, for players 1 and up; on a
+    /// client, the connection to the host.
+    peers: Vec<Connection>,
+    /// On the host, connections that have not said hello yet, with when
+    /// they were made.
+    greeting: Vec<(Connection, Instant)>,
+    player: usize,
+    players: usize,
+    level: Option<Level>,
+    delay: u32,
+    /// How many turns the local keyboard and each snake keep for the coming
+    /// ticks.
+    turn_buffer: usize,
+    sim: Option<Simulation>,
+    controller: Box<dyn SnakeController>,
+    /// On the host, the turns known so far for ticks not played yet, per
+    /// player.
+    turns: BTreeMap<u32, Vec<Option<Option<Direction>>>>,
+    /// On a client, the frames received but not played yet.
+    frames: VecDeque<(u32, Vec<Option<Direction>>)>,
+    /// On the host, its own checksum after each recent tick.
+    checksums: BTreeMap<u32, u64>,
+    /// On the host, since when the next tick has been waiting for a turn.
+    waiting: Option<Instant>,
+    last_turns: Vec<Option<Direction>>,
+    status: Status,
+}
+
+impl Session {
+    fn new(host: bool, peers: Vec<Connection>) -> Self {
+        Session {
+            host,
+            listener: None,
+            port: 0,
+            peers,
+            greeting: Vec::new(),
+            player: 0,
+            players: 1,
+            level: None,
+            delay: INPUT_DELAY,
+            turn_buffer: TURN_BUFFER,
+            sim: None,
+            controller: Box::new(KeyboardController::default()),
+            turns: BTreeMap::new(),
+            frames: VecDeque::new(),
+            checksums: BTreeMap::new(),
+            waiting: None,
+            last_turns: Vec::new(),
+            status: Status::Lobby,
+        }
+    }
+
+    /// Opens a lobby on `port` of every network interface for a game on
+    /// `level`. Port 0 picks a free one.
+    pub fn host(port: u16, level: Level, delay: u32) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        let mut session = Session::new(true, Vec::new());
+        session.port = listener.local_addr()?.port();
+        session.listener = Some(listener);
+        session.level = Some(level);
+        session.delay = delay.max(1);
+        Ok(session)
+    }
+
+    /// Connects to a host at `addr`, e.g. `127.0.0.1:7878`.
+    pub fn join(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let mut host = Connection::new(TcpStream::connect(addr)?)?;
+        host.send(&format!("hello {}", PROTOCOL));
+        Ok(Session::new(false, vec![host]))
+    }
+
+    /// Keeps up to `depth` key presses for the coming ticks instead of
+    /// [`TURN_BUFFER`], and starts games with snakes that do the same.
+    pub fn set_turn_buffer(&mut self, depth: usize) {
+        self.turn_buffer = depth.max(1);
+        self.controller = Box::new(KeyboardController::new(self.turn_buffer));
+    }
+
+    /// Lets something else than the keyboard play the local snake.
+    pub fn set_controller(&mut self, controller: Box<dyn SnakeController>) {
+        self.controller = controller;
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.host
+    }
+
+    /// The port the host listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The local player's number, counting from 0.
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    /// How many players are in the lobby or the game.
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// The game, once the host has started it.
+    pub fn sim(&self) -> Option<&Simulation> {
+        self.sim.as_ref()
+    }
+
+    /// A turn for the local snake, pressed on the keyboard.
+    pub fn press(&mut self, dir: Direction) {
+        self.controller.press(dir);
+    }
+
+    /// Sends `line` to every connected client.
+    fn broadcast(&mut self, line: &str) {
+        for peer in &mut self.peers {
+            peer.send(line);
+        }
+    }
+
+    /// Tells every client its player number and how many are waiting.
+    fn announce_lobby(&mut self) {
+        self.players = 1 + self.peers.len();
+        let players = self.players;
+        for (i, peer) in self.peers.iter_mut().enumerate() {
+            peer.send(&format!("welcome {}", i + 1));
+            peer.send(&format!("lobby {}", players));
+        }
+    }
+
+    /// Handles everything that arrived over the network. Call it often.
+    pub fn poll(&mut self) {
+        if self.host {
+            self.poll_host();
+        } else {
+            self.poll_client();
+        }
+    }
+
+    fn poll_host(&mut self) {
+        if self.status == Status::Lobby {
+            while let Some(listener) = &self.listener
+                && self.greeting.len() < MAX_PLAYERS
+                && let Ok((stream, _)) = listener.accept()
+            {
+                if let Ok(peer) = Connection::new(stream) {
+                    self.greeting.push((peer, Instant::now()));
+                }
+            }
+            // Only connections that said hello in the right version take
+            // a seat; the others are hung up on after a while.
+            let mut changed = false;
+            for (mut peer, since) in std::mem::take(&mut self.greeting) {
+                let hello = peer.receive().into_iter().find(|line| line.starts_with("hello "));
+                match hello {
+                    Some(hello) if hello != format!("hello {}", PROTOCOL) => peer.send("bye version mismatch"),
+                    Some(_) if self.peers.len() + 1 >= MAX_PLAYERS => peer.send("bye the game is full"),
+                    Some(_) => {
+                        self.peers.push(peer);
+                        changed = true;
+                    }
+                    None if peer.open && since.elapsed() < HELLO_TIMEOUT => self.greeting.push((peer, since)),
+                    None => {}
+                }
+            }
+            for peer in &mut self.peers {
+                // Nothing more is expected before the start, but reading
+                // notices when they leave.
+                peer.receive();
+            }
+            let before = self.peers.len();
+            self.peers.retain(|peer| peer.open);
+            if changed || self.peers.len() != before {
+                self.announce_lobby();
+            }
+            return;
+        }
+
+        let mut desync = None;
+        for (i, peer) in self.peers.iter_mut().enumerate() {
+            for line in peer.receive() {
+                let mut words = line.split_whitespace();
+                let (Some(kind), Some(Ok(tick))) = (words.next(), words.next().map(str::parse::<u32>)) else {
+                    continue;
+                };
+                match (kind, words.next()) {
+                    ("input", Some(turn)) => {
+                        if let Some(turn) = parse_turn(turn) {
+                            let turns = self.turns.entry(tick).or_insert_with(|| vec![None; self.players]);
+                            turns[i + 1] = Some(turn);
+                        }
+                    }
+                    ("check", Some(sum)) => {
+                        let sum = u64::from_str_radix(sum, 16).ok();
+                        if let Some(&own) = self.checksums.get(&tick)
+                            && sum != Some(own)
+                        {
+                            desync = Some(desync.map_or(tick, |t: u32| t.min(tick)));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if let Some(tick) = desync
+            && self.status == Status::Running
+        {
+            self.broadcast(&format!("desync {}", tick));
+            self.status = Status::Desync(tick);
+        }
+    }
+
+    fn poll_client(&mut self) {
+        let Some(host) = self.peers.first_mut() else {
+            return;
+        };
+        let lines = host.receive();
+        let open = host.open;
+        for line in lines {
+            let (kind, rest) = line.split_once(' ').unwrap_or((&line, ""));
+            match kind {
+                "welcome" => self.player = rest.parse().unwrap_or(self.player),
+                "lobby" => self.players = rest.parse().unwrap_or(self.players),
+                "start" => self.start_from(rest),
+                "frame" => {
+                    let mut words = rest.split_whitespace();
+                    let tick = words.next().and_then(|tick| tick.parse().ok());
+                    let turns: Option<Vec<_>> = words.map(parse_turn).collect();
+                    if let (Some(tick), Some(turns)) = (tick, turns) {
+                        self.frames.push_back((tick, turns));
+                    }
+                }
+                "desync" => self.status = Status::Desync(rest.parse().unwrap_or(0)),
+                "bye" => self.status = Status::Disconnected(format!("The host said: {}", rest)),
+                _ => {}
+            }
+        }
+        if !open && matches!(self.status, Status::Lobby | Status::Running) {
+            self.status = Status::Disconnected("Lost the connection to the host".to_string());
+        }
+    }
+
+    /// Sets up the game from the host's `start` message.
+    fn start_from(&mut self, message: &str) {
+        let mut parts = message.splitn(4, ' ');
+        let (Some(seed), Some(players), Some(delay), Some(level)) = (
+            parts.next().and_then(|s| s.parse().ok()),
+            parts.next().and_then(|s| s.parse().ok()),
+            parts.next().and_then(|s| s.parse().ok()),
+            parts.next(),
+        ) else {
+            self.status = Status::Disconnected("The host sent a broken start message".to_string());
+            return;
+        };
+        match Level::parse("network", &unescape(level)) {
+            Ok(level) => {
+                self.players = players;
+                self.delay = delay;
+                self.sim = Some(Simulation::with_players(level.clone(), seed, players).with_turn_buffer(self.turn_buffer));
+                self.level = Some(level);
+                self.status = Status::Running;
+            }
+            Err(err) => self.status = Status::Disconnected(format!("The host sent a broken level: {}", err)),
+        }
+    }
+
+    /// Starts the game on the host for everybody in the lobby. Needs at
+    /// least one client.
+    pub fn start(&mut self, seed: u64) -> bool {
+        if !self.host || self.status != Status::Lobby || self.peers.is_empty() {
+            return false;
+        }
+        let Some(level) = self.level.clone() else {
+            return false;
+        };
+        self.listener = None;
+        self.greeting.clear();
+        self.players = 1 + self.peers.len();
+        self.broadcast(&format!("start {} {} {} {}", seed, self.players, self.delay, escape(&level.to_file_string())));
+        self.sim = Some(Simulation::with_players(level, seed, self.players).with_turn_buffer(self.turn_buffer));
+        self.status = Status::Running;
+        true
+    }
+
+    /// The local player's turn for the tick [`INPUT_DELAY`] ticks after
+    /// the one just played.
+    fn schedule_own_turn(&mut self) -> (u32, Option<Direction>) {
+        let Some(sim) = &self.sim else {
+            return (0, None);
+        };
+        let tick = sim.ticks() - 1 + self.delay;
+        let alive = sim.players().get(self.player).is_some_and(|p| p.is_alive());
+        let turn = if alive { self.controller.decide(sim, self.player) } else { None };
+        (tick, turn)
+    }
+
+    /// Plays the next tick if every turn for it is known, and returns what
+    /// happened to each snake; `None` while waiting.
+    pub fn advance(&mut self) -> Option<Vec<TickOutcome>> {
+        if self.status != Status::Running {
+            return None;
+        }
+        if self.host { self.advance_host() } else { self.advance_client() }
+    }
+
+    fn advance_host(&mut self) -> Option<Vec<TickOutcome>> {
+        let tick = self.sim.as_ref()?.ticks();
+        let turns: Vec<Option<Direction>> = if tick < self.delay {
+            vec![None; self.players]
+        } else {
+            let known = self.turns.get(&tick).cloned().unwrap_or_else(|| vec![None; self.players]);
+            // Clients that left go straight on.
+            let missing: Vec<usize> = (0..self.players)
+                .filter(|&p| known[p].is_none() && (p == 0 || self.peers[p - 1].open))
+                .collect();
+            if !missing.is_empty() {
+                // One player stuck for too long should not hold up the
+                // others, so they are left behind as if they quit.
+                let since = *self.waiting.get_or_insert_with(Instant::now);
+                if since.elapsed() >= TURN_TIMEOUT {
+                    for p in missing.into_iter().filter(|&p| p > 0) {
+                        self.peers[p - 1].close();
+                    }
+                }
+                return None;
+            }
+            self.waiting = None;
+            self.turns.remove(&tick);
+            known.into_iter().map(Option::flatten).collect()
+        };
+
+        let outcomes = self.play(&turns)?;
+        let frame: Vec<String> = turns.iter().map(|&turn| turn_letter(turn).to_string()).collect();
+        self.broadcast(&format!("frame {} {}", tick, frame.join(" ")));
+
+        let sim = self.sim.as_ref()?;
+        self.checksums.insert(tick, sim.checksum());
+        self.checksums.retain(|&t, _| t + CHECKSUM_HISTORY > tick);
+        let (own_tick, own_turn) = self.schedule_own_turn();
+        let players = self.players;
+        self.turns.entry(own_tick).or_insert_with(|| vec![None; players])[0] = Some(own_turn);
+        Some(outcomes)
+    }
+
+    fn advance_client(&mut self) -> Option<Vec<TickOutcome>> {
+        let (tick, turns) = self.frames.pop_front()?;
+        if self.sim.as_ref()?.ticks() != tick || turns.len() != self.players {
+            self.status = Status::Desync(tick);
+            return None;
+        }
+        let outcomes = self.play(&turns)?;
+        let checksum = self.sim.as_ref()?.checksum();
+        let (own_tick, own_turn) = self.schedule_own_turn();
+        let host = self.peers.first_mut()?;
+        host.send(&format!("input {} {}", own_tick, turn_letter(own_turn)));
+        host.send(&format!("check {} {:x}", tick, checksum));
+        Some(outcomes)
+    }
+
+    /// Plays one tick and ends the session with the game.
+    fn play(&mut self, turns: &[Option<Direction>]) -> Option<Vec<TickOutcome>> {
+        let sim = self.sim.as_mut()?;
+        let outcomes = play(sim, turns);
+        if sim.is_over() {
+            self.status = Status::Over;
+        }
+        self.last_turns = turns.to_vec();
+        Some(outcomes)
+    }
+
+    /// The turns of the tick played last, one per player.
+    pub fn last_turns(&self) -> &[Option<Direction>] {
+        &self.last_turns
+    }
+}
+
+/// Applies one tick's turns, in player order, and plays it.
+pub fn play(sim: &mut Simulation, turns: &[Option<Direction>]) -> Vec<TickOutcome> {
+    for (player, turn) in turns.iter().enumerate() {
+        if let Some(dir) = turn {
+            sim.steer_player(player, *dir);
+        }
+    }
+    sim.tick()
+}
+
+/// Streams a running game to spectators, who watch it read-only from
+/// other processes.
+///
+/// Spectators first get the game's level and seed, then one line per
+/// tick with the turns played in it and the checksum after it, and play
+/// the game along. Those who connect late get every tick so far to catch
+/// up, unless the game has run for more than [`CATCH_UP_TICKS`], and a new
+/// game starts over for everyone. Spectators who stop reading are dropped
+/// instead of holding up the game.
+///
+/// ```text
+/// watch <seed> <players> <level>
+/// tick <tick> <turn per player> <checksum>
+/// ```
+pub struct Broadcast {
+    listener: TcpListener,
+    spectators: Vec<Connection>,
+    /// The `watch` line of the current game.
+    start: Option<String>,
+    /// Every `tick` line of the current game, for spectators who join
+    /// late, or `None` once there are too many to catch up on.
+    ticks: Option<Vec<String>>,
+    /// How many ticks of the current game were sent.
+    sent: u32,
+}
+
+impl Broadcast {
+    /// Lets spectators connect on `port` of every network interface. Port
+    /// 0 picks a free one.
+    pub fn new(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Broadcast { listener, spectators: Vec::new(), start: None, ticks: None, sent: 0 })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.listener.local_addr().map_or(0, |addr| addr.port())
+    }
+
+    /// How many spectators are watching.
+    pub fn spectators(&self) -> usize {
+        self.spectators.len()
+    }
+
+    /// Lets new spectators in and catches them up. Call it often.
+    pub fn poll(&mut self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            let Ok(mut spectator) = Connection::new(stream) else {
+                continue;
+            };
+            if let (Some(start), Some(ticks)) = (&self.start, &self.ticks) {
+                spectator.send_all(std::iter::once(start).chain(ticks));
+            }
+            self.spectators.push(spectator);
+        }
+        for spectator in &mut self.spectators {
+            // Spectators have nothing to say, but reading notices when
+            // they leave.
+            spectator.receive();
+        }
+        self.spectators.retain(|spectator| spectator.open);
+    }
+
+    /// Sends out the tick `sim` just played with `turns`. The first tick
+    /// of a game also starts it for the spectators; games already running
+    /// when the broadcast began are not sent.
+    pub fn tick(&mut self, turns: &[Option<Direction>], sim: &Simulation) {
+        if sim.ticks() == 1 {
+            let start = format!(
+                "watch {} {} {}",
+                sim.seed(),
+                sim.players().len(),
+                escape(&sim.level().to_file_string())
+            );
+            for spectator in &mut self.spectators {
+                spectator.send(&start);
+            }
+            self.start = Some(start);
+            self.ticks = Some(Vec::new());
+            self.sent = 0;
+        }
+        // Ticking a finished game does nothing, and is not sent again.
+        if self.start.is_none() || self.sent + 1 != sim.ticks() {
+            return;
+        }
+        let turns: Vec<String> = turns.iter().map(|&turn| turn_letter(turn).to_string()).collect();
+        let line = format!("tick {} {} {:x}", sim.ticks() - 1, turns.join(" "), sim.checksum());
+        for spectator in &mut self.spectators {
+            spectator.send(&line);
+        }
+        self.sent += 1;
+        if self.ticks.as_ref().is_some_and(|ticks| ticks.len() >= CATCH_UP_TICKS) {
+            self.ticks = None;
+        }
+        if let Some(ticks) = &mut self.ticks {
+            ticks.push(line);
+        }
+    }
+}
+
+/// What a spectator hears about the game it watches.
+#[derive(Debug, Clone)]
+pub enum Feed {
+    /// A new game begins.
+    Start { level: Level, seed: u64, players: usize },
+    /// `tick` was played with `turns`, leaving the game with `checksum`.
+    Tick { tick: u32, turns: Vec<Option<Direction>>, checksum: u64 },
+}
+
+/// The watching end of a [`Broadcast`].
+pub struct Spectator {
+    host: Connection,
+}
+
+impl Spectator {
+    /// Connects to a broadcast at `addr`, e.g. `127.0.0.1:7879`.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Spectator { host: Connection::new(TcpStream::connect(addr)?)? })
+    }
+
+    /// Whether the broadcast is still on.
+    pub fn is_open(&self) -> bool {
+        self.host.open
+    }
+
+    /// Everything that happened since the last call, in order. Lines that
+    /// cannot be read are skipped.
+    pub fn poll(&mut self) -> Vec<Feed> {
+        let mut feed = Vec::new();
+        for line in self.host.receive() {
+            let (kind, rest) = line.split_once(' ').unwrap_or((&line, ""));
+            match kind {
+                "watch" => {
+                    let mut parts = rest.splitn(3, ' ');
+                    let (Some(seed), Some(players), Some(level)) = (
+                        parts.next().and_then(|s| s.parse().ok()),
+                        parts.next().and_then(|s| s.parse().ok()),
+                        parts.next().and_then(|s| Level::parse("broadcast", &unescape(s)).ok()),
+                    ) else {
+                        continue;
+                    };
+                    feed.push(Feed::Start { level, seed, players });
+                }
+                "tick" => {
+                    let words: Vec<&str> = rest.split_whitespace().collect();
+                    let Some((checksum, turns)) = words.split_last() else {
+                        continue;
+                    };
+                    let (Some(tick), Ok(checksum), Some(turns)) = (
+                        turns.first().and_then(|tick| tick.parse().ok()),
+                        u64::from_str_radix(checksum, 16),
+                        turns.iter().skip(1).map(|turn| parse_turn(turn)).collect::<Option<Vec<_>>>(),
+                    ) else {
+                        continue;
+                    };
+                    feed.push(Feed::Tick { tick, turns, checksum });
+                }
+                _ => {}
+            }
+        }
+        feed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::BfsController;
+
+    /// Calls `step` until it returns true, failing after a few seconds.
+    fn wait_for(what: &str, mut step: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !step() {
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn lobby() -> Session {
+        Session::host(0, Level::basic_levels().remove(0), INPUT_DELAY).unwrap()
+    }
+
+    fn join(host: &mut Session) -> Session {
+        let mut client = Session::join(("127.0.0.1", host.port())).unwrap();
+        let players = host.players() + 1;
+        wait_for("the client to join", || {
+            host.poll();
+            client.poll();
+            host.players() == players && client.players() == players
+        });
+        client
+    }
+
+    #[test]
+    fn turn_buffer_limits_the_presses_kept() {
+        for (depth, turned_left) in [(1, false), (3, true)] {
+            let mut host = lobby();
+            host.set_turn_buffer(depth);
+            let mut client = join(&mut host);
+            assert!(host.start(7));
+            assert_eq!(host.sim().unwrap().turn_buffer(), depth);
+            host.press(Direction::Up);
+            host.press(Direction::Left);
+
+            let mut dirs = Vec::new();
+            wait_for("the turns to be played", || {
+                host.poll();
+                if host.advance().is_some() {
+                    dirs.push(host.sim().unwrap().players()[0].snake().dir);
+                }
+                client.poll();
+                while client.advance().is_some() {}
+                dirs.len() > INPUT_DELAY as usize + 2
+            });
+            assert!(dirs.contains(&Direction::Up), "depth {}: {:?}", depth, dirs);
+            assert_eq!(dirs.contains(&Direction::Left), turned_left, "depth {}: {:?}", depth, dirs);
+        }
+    }
+
+    #[test]
+    fn lockstep_over_localhost() {
+        let mut host = lobby();
+        let mut clients = vec![join(&mut host), join(&mut host)];
+        assert_eq!(clients.iter().map(Session::player).collect::<Vec<_>>(), [1, 2]);
+        host.set_controller(Box::new(BfsController));
+        for client in &mut clients {
+            client.set_controller(Box::new(BfsController));
+        }
+        assert!(host.start(42));
+
+        let ticks = 60;
+        wait_for("the game to be played", || {
+            host.poll();
+            if host.sim().is_some_and(|sim| sim.ticks() < ticks) {
+                host.advance();
+            }
+            for client in &mut clients {
+                client.poll();
+                while client.advance().is_some() {}
+            }
+            let played = host.sim().map_or(0, Simulation::ticks);
+            (played == ticks || host.status() == &Status::Over)
+                && clients.iter().all(|client| client.sim().is_some_and(|sim| sim.ticks() == played))
+        });
+        assert!(host.sim().unwrap().ticks() > INPUT_DELAY * 2);
+        // Give the host the last checksums to compare.
+        for _ in 0..50 {
+            host.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let checksum = host.sim().unwrap().checksum();
+        assert!(matches!(host.status(), Status::Running | Status::Over), "{:?}", host.status());
+        for client in &clients {
+            assert!(matches!(client.status(), Status::Running | Status::Over), "{:?}", client.status());
+            assert_eq!(client.sim().unwrap().checksum(), checksum);
+            assert_eq!(client.last_turns(), host.last_turns());
+        }
+    }
+
+    #[test]
+    fn spectators_follow_the_game() {
+        let mut broadcast = Broadcast::new(0).unwrap();
+        let addr = ("127.0.0.1", broadcast.port());
+        let mut early = Spectator::connect(addr).unwrap();
+        wait_for("the spectator to connect", || {
+            broadcast.poll();
+            broadcast.spectators() == 1
+        });
+
+        let mut sim = Simulation::with_players(Level::basic_levels().remove(0), 9, 2);
+        let play_ticks = |sim: &mut Simulation, broadcast: &mut Broadcast, count: u32| {
+            for _ in 0..count {
+                let turns: Vec<_> = (0..2).map(|player| BfsController.decide(sim, player)).collect();
+                play(sim, &turns);
+                broadcast.tick(&turns, sim);
+                broadcast.poll();
+            }
+        };
+        play_ticks(&mut sim, &mut broadcast, 20);
+        let mut late = Spectator::connect(addr).unwrap();
+        wait_for("the late spectator to connect", || {
+            broadcast.poll();
+            broadcast.spectators() == 2
+        });
+        play_ticks(&mut sim, &mut broadcast, 20);
+
+        for spectator in [&mut early, &mut late] {
+            let mut watched: Option<Simulation> = None;
+            wait_for("the spectator to catch up", || {
+                for feed in spectator.poll() {
+                    match feed {
+                        Feed::Start { level, seed, players } => {
+                            watched = Some(Simulation::with_players(level, seed, players));
+                        }
+                        Feed::Tick { tick, turns, checksum } => {
+                            let watched = watched.as_mut().expect("start before the ticks");
+                            assert_eq!(watched.ticks(), tick);
+                            play(watched, &turns);
+                            assert_eq!(watched.checksum(), checksum, "tick {}", tick);
+                        }
+                    }
+                }
+                watched.as_ref().is_some_and(|watched| watched.ticks() == sim.ticks())
+            });
+        }
+    }
+
+    #[test]
+    fn silent_connections_get_no_seat() {
+        let mut host = lobby();
+        let _silent = TcpStream::connect(("127.0.0.1", host.port())).unwrap();
+        let client = join(&mut host);
+        assert_eq!(host.players(), 2);
+        assert_eq!(client.player(), 1);
+    }
+
+    #[test]
+    fn other_versions_are_turned_away() {
+        let mut host = lobby();
+        let mut stream = TcpStream::connect(("127.0.0.1", host.port())).unwrap();
+        writeln!(stream, "hello snake-net 0").unwrap();
+        let mut reply = String::new();
+        let mut reader = BufReader::new(stream);
+        wait_for("the host to answer", || {
+            host.poll();
+            reader.get_mut().set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+            reader.read_line(&mut reply).is_ok_and(|n| n > 0)
+        });
+        assert_eq!(reply, "bye version mismatch\n");
+        assert_eq!(host.players(), 1);
+    }
+}
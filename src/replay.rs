@@ -0,0 +1,429 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::level::Level;
+use crate::sim::{Direction, Simulation, TURN_BUFFER};
+
+/// How many replays are kept in the replay folder; older ones are deleted.
+pub const MAX_REPLAYS: usize = 50;
+
+/// Playback speeds, slowest first.
+const RATES: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+const HEADER: &str = "# snake replay v1";
+
+/// Separates the replay header from the embedded level, whose own header
+/// already uses `---`.
+const LEVEL_SEPARATOR: &str = "===";
+
+/// One turn made during a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Input {
+    /// How many ticks had been played when the turn was made.
+    pub tick: u32,
+    pub player: usize,
+    pub dir: Direction,
+}
+
+/// Everything needed to play a game again: the level as it was played, the
+/// seed, the number of snakes and every turn they made.
+///
+/// The level is stored in full so a replay still works after its level file
+/// was edited, and can be shared on its own.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub level: Level,
+    pub seed: u64,
+    pub players: usize,
+    /// How many turns each snake kept for the coming ticks, which decides
+    /// whether quick presses within one tick were all made.
+    pub turn_buffer: usize,
+    /// How many ticks the game lasted.
+    pub ticks: u32,
+    /// Turns in the order they were made.
+    pub inputs: Vec<Input>,
+}
+
+fn parse_input(s: &str) -> Option<Input> {
+    let (player, s) = match s.split_once(':') {
+        Some((player, rest)) => (player.parse().ok()?, rest),
+        None => (0, s),
+    };
+    let dir = Direction::from_letter(s.chars().last()?)?;
+    Some(Input { tick: s[..s.len() - 1].parse().ok()?, player, dir })
+}
+
+impl Replay {
+    /// Writes the replay format:
+    ///
+    /// ```text
+    /// # snake replay v1
+    /// seed: 1234
+    /// players: 2
+    /// turn-buffer: 3
+    /// ticks: 212
+    /// inputs: 3U 9R 1:9D 40L
+    /// ===
+    /// name: Easy
+    /// ...
+    /// ```
+    ///
+    /// Each input is the tick count followed by `U`, `D`, `L` or `R`, with
+    /// the player number and a colon in front for every player but the first.
+    /// Replays without `turn-buffer` were played with [`TURN_BUFFER`].
+    pub fn to_file_string(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let turn = format!("{}{}", input.tick, input.dir.letter());
+                if input.player == 0 { turn } else { format!("{}:{}", input.player, turn) }
+            })
+            .collect();
+        format!(
+            "{}\nseed: {}\nplayers: {}\nturn-buffer: {}\nticks: {}\ninputs: {}\n{}\n{}",
+            HEADER,
+            self.seed,
+            self.players,
+            self.turn_buffer,
+            self.ticks,
+            inputs.join(" "),
+            LEVEL_SEPARATOR,
+            self.level.to_file_string(),
+        )
+    }
+
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let Some((header, level)) = text.split_once(&format!("\n{}\n", LEVEL_SEPARATOR)) else {
+            return Err(format!("missing `{}` line before the level", LEVEL_SEPARATOR));
+        };
+
+        let mut seed = None;
+        let mut players = 1;
+        let mut turn_buffer = TURN_BUFFER;
+        let mut ticks = None;
+        let mut inputs = Vec::new();
+        for line in header.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(format!("expected `key: value`, found `{}`", line));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "seed" => seed = Some(value.parse().map_err(|_| format!("bad seed `{}`", value))?),
+                "players" => {
+                    players = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("bad player count `{}`", value))?
+                }
+                "turn-buffer" => {
+                    turn_buffer = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("bad turn buffer `{}`", value))?
+                }
+                "ticks" => ticks = Some(value.parse().map_err(|_| format!("bad tick count `{}`", value))?),
+                "inputs" => {
+                    for input in value.split_whitespace() {
+                        inputs.push(parse_input(input).ok_or_else(|| format!("bad input `{}`", input))?);
+                    }
+                }
+                key => return Err(format!("unknown key `{}`", key)),
+            }
+        }
+
+        Ok(Replay {
+            level: Level::parse("replay", level).map_err(|err| err.to_string())?,
+            seed: seed.ok_or("missing seed")?,
+            players,
+            turn_buffer,
+            ticks: ticks.ok_or("missing tick count")?,
+            inputs,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Replay::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Saves the replay into `dir` under a name that sorts by time, then
+    /// deletes the oldest replays beyond [`MAX_REPLAYS`]. Only writing the
+    /// new replay can fail; old ones that cannot be deleted are left for the
+    /// next save to try again.
+    pub fn save_in(&self, dir: &Path, unix_secs: u64) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{:010}-{}-{}.replay", unix_secs, self.level.slug(), self.seed));
+        fs::write(&path, self.to_file_string())?;
+
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Ok(path);
+        };
+        let mut old: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+            .collect();
+        old.sort();
+        let excess = old.len().saturating_sub(MAX_REPLAYS);
+        for path in &old[..excess] {
+            let _ = fs::remove_file(path);
+        }
+        Ok(path)
+    }
+}
+
+/// Re-simulates a [`Replay`] tick by tick, with pausing, speed control and
+/// seeking. Seeking backwards replays from the start, which is quick since
+/// the simulation is cheap.
+#[derive(Debug, Clone)]
+pub struct ReplayPlayer {
+    replay: Replay,
+    sim: Simulation,
+    /// Index of the first input not applied yet.
+    next_input: usize,
+    paused: bool,
+    rate: usize,
+    /// Game time owed to the simulation, in seconds.
+    clock: f32,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayer {
+            sim: Simulation::with_players(replay.level.clone(), replay.seed, replay.players)
+                .with_turn_buffer(replay.turn_buffer),
+            replay,
+            next_input: 0,
+            paused: false,
+            rate: 2,
+            clock: 0.0,
+        }
+    }
+
+    pub fn sim(&self) -> &Simulation {
+        &self.sim
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.sim.ticks()
+    }
+
+    /// Length of the whole replay in ticks.
+    pub fn len(&self) -> u32 {
+        self.replay.ticks
+    }
+
+    /// Whether the game ended before its first tick.
+    pub fn is_empty(&self) -> bool {
+        self.replay.ticks == 0
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tick() >= self.replay.ticks || self.sim.is_over()
+    }
+
+    /// Playback speed as a multiple of the original.
+    pub fn rate(&self) -> f32 {
+        RATES[self.rate]
+    }
+
+    pub fn change_rate(&mut self, faster: bool) {
+        self.rate = if faster {
+            (self.rate + 1).min(RATES.len() - 1)
+        } else {
+            self.rate.saturating_sub(1)
+        };
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused && self.is_finished() {
+            self.seek(0);
+        }
+        self.paused = !self.paused;
+        self.clock = 0.0;
+    }
+
+    /// Plays one tick with the turns that were made before it. Returns
+    /// `false` at the end of the replay.
+    fn advance(&mut self) -> bool {
+        if self.is_finished() {
+            return false;
+        }
+        let tick = self.tick();
+        while let Some(&input) = self.replay.inputs.get(self.next_input) {
+            if input.tick > tick {
+                break;
+            }
+            self.sim.steer_player(input.player, input.dir);
+            self.next_input += 1;
+        }
+        self.sim.tick();
+        true
+    }
+
+    /// Moves to the state right after `tick` ticks were played.
+    pub fn seek(&mut self, tick: u32) {
+        if tick < self.tick() {
+            self.sim = Simulation::with_players(self.replay.level.clone(), self.replay.seed, self.replay.players)
+                .with_turn_buffer(self.replay.turn_buffer);
+            self.next_input = 0;
+        }
+        while self.tick() < tick && self.advance() {}
+    }
+
+    /// Pauses and moves one tick forwards or backwards.
+    pub fn step(&mut self, forward: bool) {
+        self.paused = true;
+        if forward {
+            self.advance();
+        } else {
+            self.seek(self.tick().saturating_sub(1));
+        }
+    }
+
+    /// Plays as many ticks as fit into `secs` of real time at the current
+    /// rate, pausing at the end.
+    pub fn update(&mut self, secs: f32) {
+        if self.paused {
+            return;
+        }
+        self.clock += secs * self.rate();
+        loop {
+            let tick_len = 1.0 / self.sim.speed();
+            if self.clock < tick_len {
+                break;
+            }
+            self.clock -= tick_len;
+            if !self.advance() {
+                self.paused = true;
+                self.clock = 0.0;
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::BfsController;
+    use crate::controller::SnakeController;
+
+    /// Plays a game with the path-finding AI on every snake, returning its
+    /// replay and the game's checksum after each tick.
+    fn record(level: Level, seed: u64, players: usize) -> (Replay, Vec<u64>) {
+        let mut sim = Simulation::with_players(level.clone(), seed, players);
+        let mut inputs = Vec::new();
+        let mut checksums = Vec::new();
+        while !sim.is_over() && sim.ticks() < 500 {
+            for player in 0..players {
+                if sim.players()[player].is_alive() && let Some(dir) = BfsController.decide(&sim, player) {
+                    inputs.push(Input { tick: sim.ticks(), player, dir });
+                    sim.steer_player(player, dir);
+                }
+            }
+            sim.tick();
+            checksums.push(sim.checksum());
+        }
+        (Replay { level, seed, players, turn_buffer: TURN_BUFFER, ticks: sim.ticks(), inputs }, checksums)
+    }
+
+    #[test]
+    fn round_trip_replays_the_same_game() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assert/levels");
+        let levels = Level::basic_levels().into_iter().chain(Level::load_dir(&dir).0);
+        for level in levels {
+            for players in [1, 2] {
+                let (replay, checksums) = record(level.clone(), 7, players);
+                assert!(!replay.inputs.is_empty());
+                let parsed = Replay::parse(&replay.to_file_string()).unwrap();
+                assert_eq!(parsed.to_file_string(), replay.to_file_string());
+
+                let mut player = ReplayPlayer::new(parsed);
+                player.seek(replay.ticks);
+                assert!(player.is_finished());
+                assert_eq!(player.sim().checksum(), *checksums.last().unwrap(), "{} with {}", level.name, players);
+
+                let middle = replay.ticks / 2;
+                player.seek(middle);
+                assert_eq!(player.sim().checksum(), checksums[middle as usize - 1], "{} with {}", level.name, players);
+            }
+        }
+    }
+
+    #[test]
+    fn replays_keep_their_turn_buffer() {
+        // Five turns in one tick, which a snake only makes with a deep
+        // enough buffer.
+        let turns = [Direction::Up, Direction::Left, Direction::Down, Direction::Right, Direction::Up];
+        let level = Level::basic_levels().remove(0);
+        let mut sim = Simulation::with_players(level.clone(), 3, 1).with_turn_buffer(5);
+        for dir in turns {
+            assert!(sim.steer_player(0, dir));
+        }
+        for _ in 0..8 {
+            sim.tick();
+        }
+        let inputs = turns.map(|dir| Input { tick: 0, player: 0, dir }).to_vec();
+        let replay = Replay { level, seed: 3, players: 1, turn_buffer: 5, ticks: sim.ticks(), inputs };
+
+        let parsed = Replay::parse(&replay.to_file_string()).unwrap();
+        assert_eq!(parsed.turn_buffer, 5);
+        let mut player = ReplayPlayer::new(parsed);
+        player.seek(replay.ticks);
+        assert_eq!(player.sim().checksum(), sim.checksum());
+
+        let old = replay.to_file_string().replace("turn-buffer: 5\n", "");
+        assert_eq!(Replay::parse(&old).unwrap().turn_buffer, TURN_BUFFER);
+    }
+
+    #[test]
+    fn saving_keeps_the_newest_replays() {
+        let dir = std::env::temp_dir().join(format!("snake-replays-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.txt"), "not a replay").unwrap();
+
+        let (replay, _) = record(Level::basic_levels().remove(0), 1, 1);
+        for secs in 1..=MAX_REPLAYS as u64 + 5 {
+            replay.save_in(&dir, secs).unwrap();
+        }
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names.len(), MAX_REPLAYS + 1);
+        assert_eq!(names[0], "0000000006-easy-1.replay");
+        assert_eq!(names[MAX_REPLAYS - 1], format!("{:010}-easy-1.replay", MAX_REPLAYS + 5));
+        assert_eq!(names[MAX_REPLAYS], "notes.txt");
+    }
+
+    #[test]
+    fn saving_succeeds_when_old_replays_cannot_be_deleted() {
+        let dir = std::env::temp_dir().join(format!("snake-replays-stuck-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // A folder named like the oldest replay cannot be removed as a file.
+        let stuck = dir.join("0000000000-easy-1.replay");
+        fs::create_dir_all(&stuck).unwrap();
+
+        let (replay, _) = record(Level::basic_levels().remove(0), 1, 1);
+        let mut saved = Vec::new();
+        for secs in 1..=MAX_REPLAYS as u64 + 1 {
+            saved.push(replay.save_in(&dir, secs));
+        }
+        let stuck_kept = stuck.is_dir();
+        let newest_kept = saved.last().unwrap().as_ref().is_ok_and(|path| path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(saved.iter().all(Result::is_ok), "{:?}", saved);
+        assert!(stuck_kept);
+        assert!(newest_kept);
+    }
+}
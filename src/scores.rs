@@ -0,0 +1,298 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many runs are kept per level and mode.
+pub const TABLE_SIZE: usize = 10;
+
+/// Longest player name kept in the table.
+pub const NAME_LEN: usize = 16;
+
+const HEADER: &str = "# snake high scores v2";
+
+/// One finished run in a high score table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreEntry {
+    pub score: u32,
+    pub length: usize,
+    pub secs: f32,
+    pub seed: u64,
+    pub name: String,
+    /// Day the run was played, as `YYYY-MM-DD`.
+    pub date: String,
+}
+
+/// Today's date in UTC as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's
+    // `civil_from_days`.
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Top runs per level and game mode, stored as a tab-separated text file.
+///
+/// A missing file starts empty tables; lines that cannot be read are skipped
+/// and reported on stderr, so a damaged file never stops the game.
+#[derive(Debug, Clone)]
+pub struct HighScores {
+    path: PathBuf,
+    /// Keyed by level name and mode name, best run first.
+    tables: BTreeMap<(String, String), Vec<ScoreEntry>>,
+}
+
+/// Tabs and newlines would break the file format, so they never end up in
+/// level or mode names on disk.
+fn clean(name: &str) -> String {
+    name.replace(['\t', '\n', '\r'], " ")
+}
+
+fn parse_line(line: &str) -> Option<((String, String), ScoreEntry)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    // v1 files had no name and date columns.
+    let (level, mode, score, length, secs, seed, name, date) = match fields[..] {
+        [level, mode, score, length, secs, seed] => (level, mode, score, length, secs, seed, "", ""),
+        [level, mode, score, length, secs, seed, name, date] => {
+            (level, mode, score, length, secs, seed, name, date)
+        }
+        _ => return None,
+    };
+    let entry = ScoreEntry {
+        score: score.parse().ok()?,
+        length: length.parse().ok()?,
+        secs: secs.parse().ok().filter(|s: &f32| s.is_finite())?,
+        seed: seed.parse().ok()?,
+        name: name.to_string(),
+        date: date.to_string(),
+    };
+    Some(((level.to_string(), mode.to_string()), entry))
+}
+
+impl HighScores {
+    /// Reads the tables from `path`. Never fails: problems are reported and
+    /// whatever could be read is kept.
+    pub fn load(path: &Path) -> Self {
+        let mut scores = HighScores { path: path.to_path_buf(), tables: BTreeMap::new() };
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return scores,
+            Err(err) => {
+                eprintln!("{}: cannot read high scores, starting fresh: {}", path.display(), err);
+                return scores;
+            }
+        };
+
+        for (no, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_line(line) {
+                Some((key, entry)) => scores.tables.entry(key).or_default().push(entry),
+                None => eprintln!("{}:{}: skipping unreadable high score line", path.display(), no + 1),
+            }
+        }
+        for table in scores.tables.values_mut() {
+            table.sort_by_key(|e| std::cmp::Reverse(e.score));
+            table.truncate(TABLE_SIZE);
+        }
+        scores
+    }
+
+    /// Writes the tables next to the target first and then renames, so a
+    /// crash halfway through cannot leave a truncated file behind.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut out = format!("{}\n", HEADER);
+        for ((level, mode), table) in &self.tables {
+            for e in table {
+                out.push_str(&format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                    level, mode, e.score, e.length, e.secs, e.seed, clean(&e.name), e.date
+                ));
+            }
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    pub fn table(&self, level: &str, mode: &str) -> &[ScoreEntry] {
+        self.tables
+            .get(&(clean(level), clean(mode)))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether a run with `score` would make it into the table.
+    pub fn qualifies(&self, level: &str, mode: &str, score: u32) -> bool {
+        let table = self.table(level, mode);
+        score > 0 && (table.len() < TABLE_SIZE || table.iter().any(|e| score > e.score))
+    }
+
+    /// Adds a run if it qualifies and returns its rank, 0 being the best.
+    pub fn insert(&mut self, level: &str, mode: &str, entry: ScoreEntry) -> Option<usize> {
+        if !self.qualifies(level, mode, entry.score) {
+            return None;
+        }
+        let table = self.tables.entry((clean(level), clean(mode))).or_default();
+        let rank = table.iter().position(|e| entry.score > e.score).unwrap_or(table.len());
+        table.insert(rank, entry);
+        table.truncate(TABLE_SIZE);
+        Some(rank)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("snake-scores-{}-{}.txt", name, std::process::id()))
+    }
+
+    fn entry(score: u32, name: &str) -> ScoreEntry {
+        ScoreEntry { score, length: 3, secs: 12.5, seed: 7, name: name.to_string(), date: "2024-05-01".to_string() }
+    }
+
+    /// Loads `text` as a high score file.
+    fn load_text(name: &str, text: &str) -> HighScores {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let scores = HighScores::load(&path);
+        fs::remove_file(&path).unwrap();
+        scores
+    }
+
+    #[test]
+    fn missing_or_unreadable_file_starts_empty() {
+        let scores = HighScores::load(&temp_path("missing"));
+        assert!(scores.table("Easy", "Classic").is_empty());
+
+        // A folder where the file should be cannot be read as one.
+        let dir = temp_path("folder");
+        fs::create_dir_all(&dir).unwrap();
+        let scores = HighScores::load(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(scores.tables.is_empty());
+
+        let path = temp_path("binary");
+        fs::write(&path, [0xff, 0xfe, 0x00, 0x9f]).unwrap();
+        let scores = HighScores::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(scores.tables.is_empty());
+    }
+
+    #[test]
+    fn corrupt_lines_are_skipped() {
+        let text = format!(
+            "{}\nEasy\tClassic\t5\t4\t3.5\t1\tAda\t2024-01-02\ngarbage\nEasy\tClassic\tlots\t4\t3.5\t1\tBob\t2024-01-02\n\
+             Easy\tClassic\t9\t4\tNaN\t1\tEve\t2024-01-02\nEasy\tClassic\t2\t4\n\u{fffd}\u{0}\n",
+            HEADER
+        );
+        let scores = load_text("corrupt", &text);
+        let table = scores.table("Easy", "Classic");
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[0].name, "Ada");
+    }
+
+    #[test]
+    fn v1_lines_load_and_save_as_v2() {
+        let scores = load_text("v1", "# snake high scores v1\nEasy\tClassic\t5\t4\t3.5\t1\n");
+        assert_eq!(
+            scores.table("Easy", "Classic"),
+            [ScoreEntry { score: 5, length: 4, secs: 3.5, seed: 1, name: String::new(), date: String::new() }]
+        );
+
+        let path = temp_path("v2");
+        let scores = HighScores { path: path.clone(), ..scores };
+        scores.save().unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let reloaded = HighScores::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(text.starts_with(HEADER), "{}", text);
+        assert_eq!(reloaded.table("Easy", "Classic"), scores.table("Easy", "Classic"));
+    }
+
+    #[test]
+    fn saved_scores_load_back() {
+        let path = temp_path("round-trip");
+        let mut scores = HighScores::load(&path);
+        scores.insert("Easy", "Classic", entry(30, "Ada"));
+        scores.insert("Easy", "Classic", entry(10, "Tab\tName"));
+        scores.insert("Hard", "Versus", entry(20, "Bob"));
+        scores.save().unwrap();
+        let reloaded = HighScores::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(reloaded.tables.len(), 2);
+        assert_eq!(reloaded.table("Easy", "Classic")[0], entry(30, "Ada"));
+        assert_eq!(reloaded.table("Easy", "Classic")[1].name, "Tab Name");
+        assert_eq!(reloaded.table("Hard", "Versus"), [entry(20, "Bob")]);
+    }
+
+    #[test]
+    fn loading_keeps_the_best_runs_in_order() {
+        let mut text = format!("{}\n", HEADER);
+        for score in 1..=TABLE_SIZE as u32 + 2 {
+            text.push_str(&format!("Easy\tClassic\t{}\t3\t1\t1\tAda\t2024-01-02\n", score));
+        }
+        let scores = load_text("truncate", &text);
+        let kept: Vec<u32> = scores.table("Easy", "Classic").iter().map(|e| e.score).collect();
+        let best: Vec<u32> = (3..=TABLE_SIZE as u32 + 2).rev().collect();
+        assert_eq!(kept, best);
+    }
+
+    #[test]
+    fn insert_ranks_and_truncates() {
+        let mut scores = HighScores::load(&temp_path("unsaved"));
+        assert!(!scores.qualifies("Easy", "Classic", 0));
+        assert_eq!(scores.insert("Easy", "Classic", entry(0, "Nil")), None);
+
+        assert_eq!(scores.insert("Easy", "Classic", entry(10, "Ada")), Some(0));
+        assert_eq!(scores.insert("Easy", "Classic", entry(20, "Bob")), Some(0));
+        // Ties go below the run that got there first.
+        assert_eq!(scores.insert("Easy", "Classic", entry(10, "Cy")), Some(2));
+        for score in 11..=TABLE_SIZE as u32 + 8 {
+            scores.insert("Easy", "Classic", entry(score, "Dee"));
+        }
+        let table = scores.table("Easy", "Classic");
+        assert_eq!(table.len(), TABLE_SIZE);
+        assert_eq!(table[0].score, 20);
+        let lowest = table[TABLE_SIZE - 1].score;
+
+        assert!(!scores.qualifies("Easy", "Classic", lowest));
+        assert!(scores.qualifies("Easy", "Classic", lowest + 1));
+        assert_eq!(scores.insert("Easy", "Classic", entry(lowest, "Eve")), None);
+        assert_eq!(scores.insert("Easy", "Classic", entry(19, "Fay")), Some(1));
+        assert_eq!(scores.table("Easy", "Classic").len(), TABLE_SIZE);
+    }
+
+    #[test]
+    fn modes_and_levels_have_their_own_tables() {
+        let mut scores = HighScores::load(&temp_path("unsaved"));
+        scores.insert("Easy", "Classic", entry(10, "Ada"));
+        scores.insert("Easy", "Versus", entry(5, "Bob"));
+        scores.insert("Hard", "Classic", entry(1, "Cy"));
+
+        assert_eq!(scores.table("Easy", "Classic"), [entry(10, "Ada")]);
+        assert_eq!(scores.table("Easy", "Versus"), [entry(5, "Bob")]);
+        assert_eq!(scores.table("Hard", "Classic"), [entry(1, "Cy")]);
+        assert!(scores.table("Hard", "Versus").is_empty());
+        // A 5 is not the best on Easy, but is on Easy in versus mode.
+        assert_eq!(scores.insert("Easy", "Versus", entry(6, "Dee")), Some(0));
+    }
+}
@@ -0,0 +1,1170 @@
+use oorandom::Rand32;
+
+use std::collections::VecDeque;
+
+use crate::level::{Level, WrapMode};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GridPos {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl GridPos {
+
+    pub fn new(x: i32, y: i32) -> Self {
+        GridPos { x, y }
+    }
+
+    /// The cell one step from `pos` in `dir` on a board of `size`, or `None`
+    /// if that step leaves the board across an edge that `wrap` does not wrap.
+    pub fn new_from_move(pos: GridPos, dir: Direction, size: (i32, i32), wrap: WrapMode) -> Option<Self> {
+        let (x, y) = match dir {
+            Direction::Up => (pos.x, pos.y - 1),
+            Direction::Down => (pos.x, pos.y + 1),
+            Direction::Left => (pos.x - 1, pos.y),
+            Direction::Right => (pos.x + 1, pos.y),
+        };
+        let wrap_x = matches!(wrap, WrapMode::Both | WrapMode::Horizontal);
+        let wrap_y = matches!(wrap, WrapMode::Both | WrapMode::Vertical);
+        if (!wrap_x && !(0..size.0).contains(&x)) || (!wrap_y && !(0..size.1).contains(&y)) {
+            return None;
+        }
+        Some(GridPos::new(x.rem_euclid(size.0), y.rem_euclid(size.1)))
+    }
+}
+
+impl From<(i32, i32)> for GridPos {
+    fn from(pos: (i32, i32)) -> Self {
+        GridPos { x: pos.0, y: pos.1 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+
+    pub const ALL: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+    pub fn inverse(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
+    /// `U`, `D`, `L` or `R`, as used in replay files and over the network.
+    pub fn letter(self) -> char {
+        match self {
+            Direction::Up => 'U',
+            Direction::Down => 'D',
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+        }
+    }
+
+    pub fn from_letter(c: char) -> Option<Self> {
+        Direction::ALL.into_iter().find(|dir| dir.letter() == c)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub pos: GridPos,
+}
+
+impl Segment {
+    pub fn new(pos: GridPos) -> Self {
+        Segment { pos }
+    }
+}
+
+/// How many ticks a special (non-normal) food stays on the board before it
+/// is replaced by a new one.
+pub const SPECIAL_FOOD_TICKS: u32 = 40;
+
+/// How many tail segments a shrink food removes.
+pub const SHRINK_SEGMENTS: usize = 3;
+
+/// Ticks-per-second change applied by speed-up and slow-down food.
+const SPEED_FACTOR: f32 = 1.25;
+const MIN_SPEED: f32 = 2.0;
+const MAX_SPEED: f32 = 30.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FoodKind {
+    /// Grows the snake by one segment.
+    Normal,
+    /// Like normal food but worth more, and only around for a while.
+    Bonus,
+    /// Removes segments from the tail.
+    Shrink,
+    /// Makes the game tick faster.
+    SpeedUp,
+    /// Makes the game tick slower.
+    SlowDown,
+}
+
+impl FoodKind {
+    pub const ALL: [FoodKind; 5] = [
+        FoodKind::Normal,
+        FoodKind::Bonus,
+        FoodKind::Shrink,
+        FoodKind::SpeedUp,
+        FoodKind::SlowDown,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FoodKind::Normal => "normal",
+            FoodKind::Bonus => "bonus",
+            FoodKind::Shrink => "shrink",
+            FoodKind::SpeedUp => "speedup",
+            FoodKind::SlowDown => "slowdown",
+        }
+    }
+
+    /// Points scored for eating this kind.
+    pub fn value(self) -> u32 {
+        match self {
+            FoodKind::Normal => 1,
+            FoodKind::Bonus => 5,
+            FoodKind::Shrink => 1,
+            FoodKind::SpeedUp => 2,
+            FoodKind::SlowDown => 1,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        FoodKind::ALL.into_iter().find(|kind| kind.name() == s)
+    }
+
+    /// Picks a kind with probability proportional to `weights`, which is
+    /// indexed like [`FoodKind::ALL`]. All-zero weights always give normal food.
+    fn pick(rng: &mut Rand32, weights: &[u32; 5]) -> Self {
+        let total: u32 = weights.iter().sum();
+        if total == 0 {
+            return FoodKind::Normal;
+        }
+        let mut roll = rng.rand_range(0..total);
+        for (kind, &weight) in FoodKind::ALL.iter().zip(weights) {
+            if roll < weight {
+                return *kind;
+            }
+            roll -= weight;
+        }
+        FoodKind::Normal
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Food {
+    pub pos: GridPos,
+    pub kind: FoodKind,
+    /// Ticks left before special food disappears; normal food stays forever.
+    pub ticks_left: Option<u32>,
+}
+
+impl Food {
+    pub fn new(pos: GridPos, kind: FoodKind) -> Self {
+        let ticks_left = (kind != FoodKind::Normal).then_some(SPECIAL_FOOD_TICKS);
+        Food { pos, kind, ticks_left }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ate {
+    Itself,
+    Food(FoodKind),
+    Obstacle,
+    Wall,
+    /// Another snake's body.
+    Rival,
+    /// Another snake's head, which kills both snakes.
+    HeadOn,
+}
+
+impl Ate {
+    /// How a game ended when the snake ran into this.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Ate::Itself => "Ran into itself",
+            Ate::Food(_) => "Filled the board",
+            Ate::Obstacle => "Hit an obstacle",
+            Ate::Wall => "Hit the wall",
+            Ate::Rival => "Ran into another snake",
+            Ate::HeadOn => "Crashed head-on",
+        }
+    }
+
+    /// Short name for statistics and machine-readable output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Ate::Itself => "itself",
+            Ate::Food(_) => "food",
+            Ate::Obstacle => "obstacle",
+            Ate::Wall => "wall",
+            Ate::Rival => "rival",
+            Ate::HeadOn => "head-on",
+        }
+    }
+}
+
+/// How many turns a snake keeps for the coming ticks, unless the game
+/// asks for another depth with [`Simulation::with_turn_buffer`].
+pub const TURN_BUFFER: usize = 3;
+
+/// Turns waiting to be made, one per tick, so quick presses like Up then
+/// Left within one tick both count.
+///
+/// Each turn is checked against the one queued before it, or the current
+/// heading when none is: going the same way again is dropped as a
+/// duplicate, and going back the opposite way is dropped as a reversal the
+/// snake could not make. Turns beyond the depth are dropped too.
+#[derive(Debug, Clone)]
+pub struct TurnQueue {
+    turns: VecDeque<Direction>,
+    depth: usize,
+}
+
+impl Default for TurnQueue {
+    fn default() -> Self {
+        TurnQueue::new(TURN_BUFFER)
+    }
+}
+
+impl TurnQueue {
+    pub fn new(depth: usize) -> Self {
+        TurnQueue { turns: VecDeque::with_capacity(depth), depth }
+    }
+
+    /// Queues `dir` for a snake heading `heading`, if known. Returns whether
+    /// it was kept.
+    pub fn push(&mut self, dir: Direction, heading: Option<Direction>) -> bool {
+        let last = self.turns.back().copied().or(heading);
+        if self.turns.len() >= self.depth || last.is_some_and(|last| dir == last || dir == last.inverse()) {
+            return false;
+        }
+        self.turns.push_back(dir);
+        true
+    }
+
+    /// The turn for the coming tick.
+    pub fn pop(&mut self) -> Option<Direction> {
+        self.turns.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.turns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.turns.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Direction> + '_ {
+        self.turns.iter().copied()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Snake {
+    pub head: Segment,
+    pub dir: Direction,
+    pub body: VecDeque<Segment>,
+    pub ate: Option<Ate>,
+    turns: TurnQueue,
+}
+
+impl Snake {
+    /// A two-cell snake with its head on `pos`, keeping up to `turn_buffer`
+    /// turns for the coming ticks.
+    pub fn new(pos: GridPos, dir: Direction, size: (i32, i32), turn_buffer: usize) -> Self {
+        let mut body = VecDeque::new();
+
+        let tail = GridPos::new_from_move(pos, dir.inverse(), size, WrapMode::Both).unwrap_or(pos);
+        body.push_back(Segment::new(tail));
+        Snake {
+            head: Segment::new(pos),
+            dir,
+            body,
+            ate: None,
+            turns: TurnQueue::new(turn_buffer),
+        }
+    }
+
+    fn eats(&self, food: &Food) -> bool {
+        self.head.pos == food.pos
+    }
+
+    /// Number of cells the snake covers, head included. Never zero, so
+    /// there is no `is_empty`.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.body.len() + 1
+    }
+
+    /// Every cell the snake covers, head first.
+    pub fn cells(&self) -> impl Iterator<Item = GridPos> + '_ {
+        std::iter::once(self.head.pos).chain(self.body.iter().map(|seg| seg.pos))
+    }
+
+    fn eats_self(&self) -> bool {
+        for seg in &self.body {
+            if self.head.pos == seg.pos {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Queues a turn; the snake makes one queued turn per tick. Returns
+    /// whether the turn was kept.
+    fn steer(&mut self, dir: Direction) -> bool {
+        self.turns.push(dir, Some(self.dir))
+    }
+
+    fn hits_obstacle(&self, obstacles: &[GridPos]) -> bool {
+        obstacles.contains(&self.head.pos)
+    }
+
+    fn update(&mut self, food: Option<&Food>, level: &Level) {
+
+        if let Some(dir) = self.turns.pop() {
+            self.dir = dir;
+        }
+
+        let Some(new_head_pos) = GridPos::new_from_move(self.head.pos, self.dir, level.size, level.wrap) else {
+            self.ate = Some(Ate::Wall);
+            return;
+        };
+
+        let new_head = Segment::new(new_head_pos);
+
+        self.body.push_front(self.head);
+
+        self.head = new_head;
+
+        if self.eats_self() {
+            self.ate = Some(Ate::Itself);
+        } else if self.hits_obstacle(&level.obstacles) {
+            self.ate = Some(Ate::Obstacle);
+        } else if let Some(food) = food.filter(|food| self.eats(food)) {
+            self.ate = Some(Ate::Food(food.kind));
+        } else {
+            self.ate = None;
+        }
+
+        if self.ate.is_none() {
+            self.body.pop_back();
+        }
+    }
+}
+
+/// Picks a cell for new food uniformly among the cells not covered by a
+/// snake or a wall, or `None` if the board is full.
+///
+/// This walks the whole board once, so it costs the same on an empty board
+/// as on an almost full one, where sampling random cells would keep missing.
+/// Walls outside the board, which only a level failing [`Level::validate`]
+/// can have, are ignored.
+pub fn place_food<'a>(
+    rng: &mut Rand32,
+    level: &Level,
+    snakes: impl IntoIterator<Item = &'a Snake>,
+) -> Option<GridPos> {
+    let (w, h) = level.size;
+    let index = |pos: GridPos| (pos.y * w + pos.x) as usize;
+    let on_board = |pos: &GridPos| (0..w).contains(&pos.x) && (0..h).contains(&pos.y);
+
+    let mut taken = vec![false; (w * h) as usize];
+    for pos in snakes.into_iter().flat_map(Snake::cells).chain(level.obstacles.iter().copied()).filter(on_board) {
+        taken[index(pos)] = true;
+    }
+    let free = taken.iter().filter(|&&t| !t).count();
+    if free == 0 {
+        return None;
+    }
+
+    let pick = rng.rand_range(0..free as u32) as usize;
+    let cell = taken
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| !**t)
+        .nth(pick)
+        .map(|(i, _)| i as i32)?;
+    Some(GridPos::new(cell % w, cell / w))
+}
+
+/// Places a new food of a kind picked with the level's spawn weights.
+fn new_food<'a>(
+    rng: &mut Rand32,
+    level: &Level,
+    snakes: impl IntoIterator<Item = &'a Snake>,
+) -> Option<Food> {
+    let pos = place_food(rng, level, snakes)?;
+    Some(Food::new(pos, FoodKind::pick(rng, &level.food_weights)))
+}
+
+/// What happened to one snake during a single tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickOutcome {
+    /// The snake moved one cell without touching anything.
+    Moved,
+    /// The snake's head ran into something; see [`Ate`].
+    Ate(Ate),
+    /// The snake ate the last food and now covers every free cell.
+    BoardFull,
+    /// The game had already ended, or this snake was already dead, so
+    /// nothing moved.
+    GameOver,
+}
+
+/// One snake in a game, with its score and how it ended.
+#[derive(Debug, Clone)]
+pub struct Player {
+    snake: Snake,
+    score: u32,
+    /// Food eaten so far, indexed like [`FoodKind::ALL`].
+    eaten: [u32; 5],
+    /// What the snake ran into, once it is dead.
+    death: Option<Ate>,
+    /// The tick the snake died on.
+    died_at: Option<u32>,
+}
+
+impl Player {
+    fn new(snake: Snake) -> Self {
+        Player { snake, score: 0, eaten: [0; 5], death: None, died_at: None }
+    }
+
+    pub fn snake(&self) -> &Snake {
+        &self.snake
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// How many pieces of `kind` this snake has eaten.
+    pub fn eaten(&self, kind: FoodKind) -> u32 {
+        self.eaten[kind as usize]
+    }
+
+    /// What killed the snake, or `None` while it is alive.
+    pub fn death(&self) -> Option<Ate> {
+        self.death
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.death.is_none()
+    }
+}
+
+/// The snake rules without any window, audio or timer attached.
+///
+/// Everything is driven through [`Simulation::tick`], one call per tick, so
+/// the same seed and inputs always produce the same game.
+///
+/// A game has one or more snakes that all move at once and share the food.
+/// With several snakes the game ends when at most one is left alive.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    level: Level,
+    players: Vec<Player>,
+    food: Option<Food>,
+    speed: f32,
+    /// Seconds of game time, i.e. the sum of each tick's length at the speed
+    /// it was played at.
+    elapsed: f32,
+    /// Ticks played so far.
+    ticks: u32,
+    rng: Rand32,
+    seed: u64,
+    /// How many turns each snake keeps for the coming ticks.
+    turn_buffer: usize,
+    gameover: bool,
+    won: bool,
+}
+
+impl Simulation {
+
+    /// Starts a game with `count` snakes, placed by [`Level::spawns`].
+    pub fn with_players(level: Level, seed: u64, count: usize) -> Self {
+        Simulation::start(level, seed, count, TURN_BUFFER)
+    }
+
+    /// Starts the same game over with snakes that keep up to `depth` turns
+    /// for the coming ticks instead of [`TURN_BUFFER`]. Meant to be chained
+    /// onto [`Simulation::with_players`]; a depth of 0 counts as 1.
+    pub fn with_turn_buffer(self, depth: usize) -> Self {
+        Simulation::start(self.level, self.seed, self.players.len(), depth.max(1))
+    }
+
+    fn start(level: Level, seed: u64, count: usize, turn_buffer: usize) -> Self {
+
+        let mut rng = Rand32::new(seed);
+
+        let players: Vec<Player> = level
+            .spawns(count)
+            .into_iter()
+            .map(|(pos, dir)| Player::new(Snake::new(pos, dir, level.size, turn_buffer)))
+            .collect();
+
+        let food = match level.food {
+            Some(pos) => Some(Food::new(pos, FoodKind::Normal)),
+            None => new_food(&mut rng, &level, players.iter().map(Player::snake)),
+        };
+
+        Simulation {
+            players,
+            food,
+            speed: level.speed,
+            elapsed: 0.0,
+            ticks: 0,
+            level,
+            gameover: false,
+            won: false,
+            rng,
+            seed,
+            turn_buffer,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// How many turns each snake keeps for the coming ticks.
+    pub fn turn_buffer(&self) -> usize {
+        self.turn_buffer
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    pub fn players(&self) -> &[Player] {
+        &self.players
+    }
+
+    /// The first player's snake; the only one in a single-player game.
+    pub fn snake(&self) -> &Snake {
+        &self.players[0].snake
+    }
+
+    pub fn food(&self) -> Option<&Food> {
+        self.food.as_ref()
+    }
+
+    /// Current ticks per second; starts at the level's speed and is changed
+    /// by speed-up and slow-down food.
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// The first player's score.
+    pub fn score(&self) -> u32 {
+        self.players[0].score
+    }
+
+    pub fn elapsed_secs(&self) -> f32 {
+        self.elapsed
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    /// A hash of everything that decides how the game goes on, so two
+    /// copies of a game can be checked for having drifted apart. Turns still
+    /// queued are left out, as they only count once a tick plays them.
+    pub fn checksum(&self) -> u64 {
+        // FNV-1a, which is stable across platforms and Rust versions,
+        // unlike `std::hash`.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut add = |value: u64| {
+            for byte in value.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        let (state, inc) = self.rng.state();
+        add(state);
+        add(inc);
+        add(self.ticks as u64);
+        add(self.speed.to_bits() as u64);
+        add(self.gameover as u64 | (self.won as u64) << 1);
+        if let Some(food) = &self.food {
+            add(food.pos.x as u64);
+            add(food.pos.y as u64);
+            add(food.kind as u64);
+            add(food.ticks_left.map_or(u64::MAX, u64::from));
+        }
+        for player in &self.players {
+            add(player.score as u64);
+            add(player.died_at.map_or(u64::MAX, u64::from));
+            add(player.snake.dir as u64);
+            for pos in player.snake.cells() {
+                add(pos.x as u64);
+                add(pos.y as u64);
+            }
+        }
+        hash
+    }
+
+    /// How many pieces of `kind` the first player has eaten.
+    pub fn eaten(&self, kind: FoodKind) -> u32 {
+        self.players[0].eaten(kind)
+    }
+
+    /// What killed the first player, or `None` while it is alive.
+    pub fn death(&self) -> Option<Ate> {
+        self.players[0].death
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.gameover
+    }
+
+    /// Whether the game ended because the snakes filled the whole board.
+    pub fn is_won(&self) -> bool {
+        self.won
+    }
+
+    /// The winner of a finished game with several snakes: the last one
+    /// alive, or the best score among those that died last. `None` while
+    /// the game runs, with a single snake, and on a draw.
+    pub fn winner(&self) -> Option<usize> {
+        if !self.gameover || self.players.len() < 2 {
+            return None;
+        }
+        let lasted = |p: &Player| p.died_at.unwrap_or(u32::MAX);
+        let last = self.players.iter().map(lasted).max()?;
+        let finalists: Vec<usize> = (0..self.players.len())
+            .filter(|&i| lasted(&self.players[i]) == last)
+            .collect();
+        let best = finalists.iter().map(|&i| self.players[i].score).max()?;
+        let mut top = finalists.into_iter().filter(|&i| self.players[i].score == best);
+        match (top.next(), top.next()) {
+            (Some(i), None) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// Queues a turn for `player`'s snake, which makes one queued turn per
+    /// tick. Returns whether the turn was kept: duplicates, reversals, turns
+    /// past [`Simulation::turn_buffer`] and unknown players are ignored.
+    pub fn steer_player(&mut self, player: usize, dir: Direction) -> bool {
+        self.players.get_mut(player).is_some_and(|player| player.snake.steer(dir))
+    }
+
+    /// Plays one tick of a single-player game, turning the snake first if
+    /// `input` is given. Shorthand for [`Simulation::steer_player`] and
+    /// [`Simulation::tick`] on the first player.
+    pub fn step(&mut self, input: Option<Direction>) -> TickOutcome {
+        if let Some(dir) = input {
+            self.steer_player(0, dir);
+        }
+        self.tick()[0]
+    }
+
+    /// Advances the game by one tick and reports what happened to each
+    /// snake, in player order.
+    ///
+    /// Every living snake moves first; collisions between snakes are then
+    /// judged on the new positions. Two heads meeting kill both snakes, and
+    /// a head running into another snake's body kills that head's snake.
+    pub fn tick(&mut self) -> Vec<TickOutcome> {
+
+        if self.gameover {
+            return vec![TickOutcome::GameOver; self.players.len()];
+        }
+
+        self.elapsed += 1.0 / self.speed;
+        self.ticks += 1;
+
+        for player in self.players.iter_mut().filter(|p| p.is_alive()) {
+            player.snake.update(self.food.as_ref(), &self.level);
+        }
+
+        let hits: Vec<Option<Ate>> = (0..self.players.len())
+            .map(|i| self.players[i].is_alive().then(|| self.collision(i)).flatten())
+            .collect();
+
+        let mut outcomes = Vec::with_capacity(self.players.len());
+        let mut fed = false;
+        for (player, hit) in self.players.iter_mut().zip(hits) {
+            if !player.is_alive() {
+                outcomes.push(TickOutcome::GameOver);
+                continue;
+            }
+            match hit {
+                Some(Ate::Food(kind)) => {
+                    fed = true;
+                    player.score += kind.value();
+                    player.eaten[kind as usize] += 1;
+                    match kind {
+                        FoodKind::Normal | FoodKind::Bonus => {}
+                        FoodKind::Shrink => {
+                            // Undo this tick's growth, then cut the tail,
+                            // always leaving at least one body segment.
+                            let body = &mut player.snake.body;
+                            let keep = body.len().saturating_sub(1 + SHRINK_SEGMENTS).max(1);
+                            body.truncate(keep);
+                        }
+                        FoodKind::SpeedUp => self.speed = (self.speed * SPEED_FACTOR).min(MAX_SPEED),
+                        FoodKind::SlowDown => self.speed = (self.speed / SPEED_FACTOR).max(MIN_SPEED),
+                    }
+                    outcomes.push(TickOutcome::Ate(Ate::Food(kind)));
+                }
+                Some(death) => {
+                    player.death = Some(death);
+                    player.died_at = Some(self.ticks);
+                    outcomes.push(TickOutcome::Ate(death));
+                }
+                None => outcomes.push(TickOutcome::Moved),
+            }
+        }
+
+        if fed {
+            let alive = self.players.iter().filter(|p| p.is_alive()).map(Player::snake);
+            self.food = new_food(&mut self.rng, &self.level, alive);
+            if self.food.is_none() {
+                self.gameover = true;
+                self.won = true;
+                for outcome in &mut outcomes {
+                    if let TickOutcome::Ate(Ate::Food(_)) = outcome {
+                        *outcome = TickOutcome::BoardFull;
+                    }
+                }
+                return outcomes;
+            }
+        } else {
+            self.expire_food();
+        }
+
+        let alive = self.players.iter().filter(|p| p.is_alive()).count();
+        let needed = if self.players.len() > 1 { 2 } else { 1 };
+        if alive < needed {
+            self.gameover = true;
+        }
+        outcomes
+    }
+
+    /// What player `i`'s snake ran into this tick, counting the other
+    /// snakes on top of what [`Snake::update`] found.
+    fn collision(&self, i: usize) -> Option<Ate> {
+        let snake = &self.players[i].snake;
+        if !matches!(snake.ate, None | Some(Ate::Food(_))) {
+            return snake.ate;
+        }
+        let head = snake.head.pos;
+        let others = self
+            .players
+            .iter()
+            .enumerate()
+            .filter(|&(j, p)| j != i && p.is_alive())
+            .map(|(_, p)| &p.snake);
+        let mut ate = snake.ate;
+        for other in others {
+            if other.head.pos == head {
+                return Some(Ate::HeadOn);
+            }
+            if other.body.iter().any(|seg| seg.pos == head) {
+                ate = Some(Ate::Rival);
+            }
+        }
+        ate
+    }
+
+    /// Counts down special food and swaps it for a new one when it runs out.
+    fn expire_food(&mut self) {
+        let Some(ticks) = self.food.as_mut().and_then(|food| food.ticks_left.as_mut()) else {
+            return;
+        };
+        *ticks = ticks.saturating_sub(1);
+        if *ticks == 0 {
+            let alive = self.players.iter().filter(|p| p.is_alive()).map(Player::snake);
+            self.food = new_food(&mut self.rng, &self.level, alive);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{KeyboardController, SnakeController};
+
+    fn easy_level() -> Level {
+        Level::basic_levels()[0].clone()
+    }
+
+    fn easy_game() -> Simulation {
+        Simulation::with_players(easy_level(), 1, 1)
+    }
+
+    /// Where the head went on each of the next `ticks` ticks.
+    fn moves(sim: &mut Simulation, ticks: usize) -> Vec<Direction> {
+        (0..ticks)
+            .map(|_| {
+                sim.tick();
+                sim.snake().dir
+            })
+            .collect()
+    }
+
+    #[test]
+    fn u_turn_within_one_tick_takes_two_ticks() {
+        let mut sim = easy_game();
+        let start = sim.snake().head.pos;
+        sim.steer_player(0, Direction::Up);
+        sim.steer_player(0, Direction::Left);
+        assert_eq!(moves(&mut sim, 3), [Direction::Up, Direction::Left, Direction::Left]);
+        assert!(sim.players()[0].is_alive());
+        assert_eq!(sim.snake().head.pos, GridPos::new(start.x - 2, start.y - 1));
+    }
+
+    #[test]
+    fn reversal_is_dropped() {
+        let mut sim = easy_game();
+        sim.steer_player(0, Direction::Left);
+        assert_eq!(moves(&mut sim, 2), [Direction::Right, Direction::Right]);
+        assert!(sim.players()[0].is_alive());
+    }
+
+    #[test]
+    fn reversal_of_a_queued_turn_is_dropped() {
+        let mut sim = easy_game();
+        assert!(sim.steer_player(0, Direction::Up));
+        assert!(!sim.steer_player(0, Direction::Down));
+        assert!(sim.steer_player(0, Direction::Left));
+        assert_eq!(moves(&mut sim, 3), [Direction::Up, Direction::Left, Direction::Left]);
+        assert!(sim.players()[0].is_alive());
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        let mut queue = TurnQueue::default();
+        assert!(queue.push(Direction::Up, Some(Direction::Right)));
+        assert!(!queue.push(Direction::Up, Some(Direction::Right)));
+        assert!(queue.push(Direction::Left, Some(Direction::Right)));
+        assert!(!queue.push(Direction::Left, Some(Direction::Right)));
+        assert_eq!(queue.iter().collect::<Vec<_>>(), [Direction::Up, Direction::Left]);
+
+        let mut queue = TurnQueue::default();
+        assert!(!queue.push(Direction::Right, Some(Direction::Right)));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        let mut queue = TurnQueue::new(3);
+        for dir in [Direction::Up, Direction::Left, Direction::Down] {
+            assert!(queue.push(dir, Some(Direction::Right)));
+        }
+        assert!(!queue.push(Direction::Right, Some(Direction::Right)));
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.pop(), Some(Direction::Up));
+        assert!(queue.push(Direction::Right, Some(Direction::Right)));
+    }
+
+    #[test]
+    fn full_circle_within_one_tick() {
+        let mut sim = easy_game();
+        let start = sim.snake().head.pos;
+        for dir in [Direction::Up, Direction::Left, Direction::Down, Direction::Right] {
+            sim.steer_player(0, dir);
+        }
+        // The fourth turn does not fit into the queue.
+        assert_eq!(moves(&mut sim, 4), [Direction::Up, Direction::Left, Direction::Down, Direction::Down]);
+        assert_eq!(sim.snake().head.pos, GridPos::new(start.x - 1, start.y + 1));
+    }
+
+    #[test]
+    fn turn_buffer_sets_how_many_turns_are_kept() {
+        let turns = [Direction::Up, Direction::Left, Direction::Down, Direction::Right, Direction::Up, Direction::Left];
+
+        let mut sim = easy_game().with_turn_buffer(1);
+        assert_eq!(sim.turn_buffer(), 1);
+        assert_eq!(turns.map(|dir| sim.steer_player(0, dir)), [true, false, false, false, false, false]);
+        assert_eq!(moves(&mut sim, 2), [Direction::Up, Direction::Up]);
+
+        let mut sim = easy_game().with_turn_buffer(5);
+        assert_eq!(turns.map(|dir| sim.steer_player(0, dir)), [true, true, true, true, true, false]);
+        assert_eq!(moves(&mut sim, 6), [Direction::Up, Direction::Left, Direction::Down, Direction::Right, Direction::Up, Direction::Up]);
+    }
+
+    #[test]
+    fn keyboard_keeps_as_many_presses_as_its_depth() {
+        for (depth, made) in [(1, vec![Direction::Up, Direction::Up]), (4, vec![Direction::Up, Direction::Left, Direction::Down, Direction::Right])] {
+            let mut sim = easy_game();
+            let mut keyboard = KeyboardController::new(depth);
+            for dir in [Direction::Up, Direction::Left, Direction::Down, Direction::Right] {
+                keyboard.press(dir);
+            }
+            let mut seen = Vec::new();
+            for _ in 0..made.len() {
+                if let Some(dir) = keyboard.decide(&sim, 0) {
+                    sim.steer_player(0, dir);
+                }
+                sim.tick();
+                seen.push(sim.snake().dir);
+            }
+            assert_eq!(seen, made, "depth {}", depth);
+        }
+    }
+
+    #[test]
+    fn keyboard_keeps_rapid_presses_for_later_ticks() {
+        let mut sim = easy_game();
+        let mut keyboard = KeyboardController::default();
+        for dir in [Direction::Up, Direction::Down, Direction::Left, Direction::Left, Direction::Down] {
+            keyboard.press(dir);
+        }
+        let mut made = Vec::new();
+        for _ in 0..4 {
+            if let Some(dir) = keyboard.decide(&sim, 0) {
+                sim.steer_player(0, dir);
+            }
+            sim.tick();
+            made.push(sim.snake().dir);
+        }
+        assert_eq!(made, [Direction::Up, Direction::Left, Direction::Down, Direction::Down]);
+        assert!(sim.players()[0].is_alive());
+    }
+
+    #[test]
+    fn keyboard_drops_a_reversal_of_the_heading() {
+        let mut sim = easy_game();
+        let mut keyboard = KeyboardController::default();
+        assert_eq!(keyboard.decide(&sim, 0), None);
+        sim.tick();
+        keyboard.press(Direction::Left);
+        keyboard.press(Direction::Up);
+        assert_eq!(keyboard.decide(&sim, 0), Some(Direction::Up));
+        assert_eq!(keyboard.decide(&sim, 0), None);
+    }
+
+    #[test]
+    fn moves_one_cell_per_tick() {
+        let mut sim = easy_game();
+        assert_eq!(sim.snake().head.pos, GridPos::new(7, 10));
+        assert_eq!(sim.step(None), TickOutcome::Moved);
+        assert_eq!(sim.snake().head.pos, GridPos::new(8, 10));
+        assert_eq!(sim.step(Some(Direction::Down)), TickOutcome::Moved);
+        assert_eq!(sim.snake().head.pos, GridPos::new(8, 11));
+        assert_eq!(sim.snake().cells().collect::<Vec<_>>(), [GridPos::new(8, 11), GridPos::new(8, 10)]);
+        assert_eq!(sim.ticks(), 2);
+    }
+
+    #[test]
+    fn eating_grows_and_scores() {
+        let mut level = easy_level();
+        level.food = Some(GridPos::new(8, 10));
+        let mut sim = Simulation::with_players(level, 1, 1);
+        assert_eq!(sim.step(None), TickOutcome::Ate(Ate::Food(FoodKind::Normal)));
+        assert_eq!(sim.snake().len(), 3);
+        assert_eq!(sim.score(), 1);
+        assert_eq!(sim.eaten(FoodKind::Normal), 1);
+        let food = sim.food().expect("new food").pos;
+        assert!(!sim.snake().cells().any(|pos| pos == food));
+
+        assert_eq!(sim.step(None), TickOutcome::Moved);
+        assert_eq!(sim.snake().len(), 3);
+    }
+
+    #[test]
+    fn wall_kills_without_wrapping() {
+        let mut level = easy_level();
+        level.wrap = WrapMode::Off;
+        level.spawn = GridPos::new(28, 10);
+        let mut sim = Simulation::with_players(level, 1, 1);
+        assert_eq!(sim.step(None), TickOutcome::Moved);
+        assert_eq!(sim.step(None), TickOutcome::Ate(Ate::Wall));
+        assert_eq!(sim.death(), Some(Ate::Wall));
+        assert!(sim.is_over());
+        assert_eq!(sim.step(None), TickOutcome::GameOver);
+        assert_eq!(sim.snake().head.pos, GridPos::new(29, 10));
+    }
+
+    #[test]
+    fn wraps_around_the_edges() {
+        let mut level = easy_level();
+        level.spawn = GridPos::new(29, 0);
+        let mut sim = Simulation::with_players(level.clone(), 1, 1);
+        assert_eq!(sim.step(None), TickOutcome::Moved);
+        assert_eq!(sim.snake().head.pos, GridPos::new(0, 0));
+        assert_eq!(sim.step(Some(Direction::Up)), TickOutcome::Moved);
+        assert_eq!(sim.snake().head.pos, GridPos::new(0, 19));
+
+        level.wrap = WrapMode::Horizontal;
+        let mut sim = Simulation::with_players(level, 1, 1);
+        assert_eq!(sim.step(None), TickOutcome::Moved);
+        assert_eq!(sim.step(Some(Direction::Up)), TickOutcome::Ate(Ate::Wall));
+    }
+
+    #[test]
+    fn obstacle_kills() {
+        let mut level = easy_level();
+        level.obstacles = vec![GridPos::new(9, 10)];
+        let mut sim = Simulation::with_players(level, 1, 1);
+        assert_eq!(sim.step(None), TickOutcome::Moved);
+        assert_eq!(sim.step(None), TickOutcome::Ate(Ate::Obstacle));
+        assert_eq!(sim.death(), Some(Ate::Obstacle));
+        assert!(sim.is_over());
+    }
+
+    #[test]
+    fn running_into_itself_kills() {
+        let mut sim = easy_game();
+        // Head at (7, 10) going right, the body curling round below it.
+        sim.players[0].snake.body = [(6, 10), (6, 11), (7, 11), (8, 11)]
+            .into_iter()
+            .map(|pos| Segment::new(pos.into()))
+            .collect();
+        assert_eq!(sim.step(Some(Direction::Down)), TickOutcome::Ate(Ate::Itself));
+        assert_eq!(sim.death(), Some(Ate::Itself));
+        assert!(sim.is_over());
+    }
+
+    /// A 5x5 walled board that is all wall except for the cells in `free`.
+    fn walled_level(free: &[(i32, i32)]) -> Level {
+        let mut level = easy_level();
+        level.size = (5, 5);
+        level.wrap = WrapMode::Off;
+        level.spawn = GridPos::new(1, 0);
+        level.obstacles = (0..5)
+            .flat_map(|y| (0..5).map(move |x| GridPos::new(x, y)))
+            .filter(|pos| !free.contains(&(pos.x, pos.y)))
+            .collect();
+        level
+    }
+
+    #[test]
+    fn food_lands_only_on_free_cells() {
+        // The snake covers (1, 0) and (0, 0), leaving three free cells.
+        let level = walled_level(&[(0, 0), (1, 0), (2, 0), (2, 1), (4, 4)]);
+        let snake = Snake::new(level.spawn, level.direction, level.size, TURN_BUFFER);
+        let mut seen = Vec::new();
+        for seed in 0..200 {
+            let pos = place_food(&mut Rand32::new(seed), &level, [&snake]).expect("free cell");
+            if !seen.contains(&pos) {
+                seen.push(pos);
+            }
+        }
+        seen.sort_by_key(|pos| (pos.y, pos.x));
+        assert_eq!(seen, [GridPos::new(2, 0), GridPos::new(2, 1), GridPos::new(4, 4)]);
+    }
+
+    #[test]
+    fn full_board_is_reported() {
+        let level = walled_level(&[(0, 0), (1, 0)]);
+        let snake = Snake::new(level.spawn, level.direction, level.size, TURN_BUFFER);
+        assert_eq!(place_food(&mut Rand32::new(1), &level, [&snake]), None);
+
+        let mut level = walled_level(&[(0, 0), (1, 0), (2, 0)]);
+        level.food = Some(GridPos::new(2, 0));
+        let mut sim = Simulation::with_players(level, 1, 1);
+        assert_eq!(sim.step(None), TickOutcome::BoardFull);
+        assert!(sim.is_over());
+        assert!(sim.is_won());
+        assert!(sim.food().is_none());
+        assert_eq!(sim.snake().len(), 3);
+    }
+
+    #[test]
+    fn walls_outside_the_board_are_ignored() {
+        let mut level = easy_level();
+        level.obstacles.push(GridPos::new(30, 19));
+        level.obstacles.push(GridPos::new(-1, 0));
+        let mut sim = Simulation::with_players(level, 1, 1);
+        assert_eq!(sim.step(None), TickOutcome::Moved);
+    }
+
+    /// A game on Easy with food of `kind` right in front of the snake.
+    fn food_ahead(kind: FoodKind) -> Simulation {
+        let mut sim = easy_game();
+        sim.food = Some(Food::new(GridPos::new(8, 10), kind));
+        sim
+    }
+
+    #[test]
+    fn food_kinds_follow_the_level_weights() {
+        let mut level = easy_level();
+        level.food_weights = [0, 0, 1, 0, 0];
+        for seed in 0..20 {
+            let sim = Simulation::with_players(level.clone(), seed, 1);
+            assert_eq!(sim.food().map(|food| food.kind), Some(FoodKind::Shrink));
+        }
+
+        level.food_weights = [0; 5];
+        let sim = Simulation::with_players(level, 1, 1);
+        assert_eq!(sim.food().map(|food| food.kind), Some(FoodKind::Normal));
+    }
+
+    #[test]
+    fn bonus_food_scores_more_and_expires() {
+        let mut sim = food_ahead(FoodKind::Bonus);
+        assert_eq!(sim.step(None), TickOutcome::Ate(Ate::Food(FoodKind::Bonus)));
+        assert_eq!(sim.score(), FoodKind::Bonus.value());
+        assert_eq!(sim.snake().len(), 3);
+        assert_eq!(sim.eaten(FoodKind::Bonus), 1);
+
+        // Out of the snake's way, it lasts a while and is then replaced.
+        let mut sim = food_ahead(FoodKind::Bonus);
+        sim.food.as_mut().unwrap().pos = GridPos::new(0, 0);
+        for _ in 1..SPECIAL_FOOD_TICKS {
+            assert_eq!(sim.step(None), TickOutcome::Moved);
+        }
+        let food = sim.food().unwrap();
+        assert_eq!((food.pos, food.ticks_left), (GridPos::new(0, 0), Some(1)));
+        sim.step(None);
+        let food = sim.food().unwrap();
+        assert!(matches!(food.ticks_left, None | Some(SPECIAL_FOOD_TICKS)), "{:?}", food);
+    }
+
+    #[test]
+    fn shrink_food_cuts_the_tail() {
+        let mut sim = food_ahead(FoodKind::Shrink);
+        sim.players[0].snake.body = (1..=6).map(|i| Segment::new(GridPos::new(7 - i, 10))).collect();
+        assert_eq!(sim.snake().len(), 7);
+        assert_eq!(sim.step(None), TickOutcome::Ate(Ate::Food(FoodKind::Shrink)));
+        assert_eq!(sim.snake().len(), 7 - SHRINK_SEGMENTS);
+        assert_eq!(sim.score(), FoodKind::Shrink.value());
+
+        // A short snake keeps its head and one segment.
+        let mut sim = food_ahead(FoodKind::Shrink);
+        sim.step(None);
+        assert_eq!(sim.snake().len(), 2);
+    }
+
+    #[test]
+    fn speed_food_changes_the_tick_rate() {
+        let mut sim = food_ahead(FoodKind::SpeedUp);
+        sim.step(None);
+        assert_eq!(sim.speed(), 5.0 * 1.25);
+        assert_eq!(sim.snake().len(), 3);
+
+        let mut sim = food_ahead(FoodKind::SlowDown);
+        sim.step(None);
+        assert_eq!(sim.speed(), 5.0 / 1.25);
+
+        // Never slower than the slowest speed.
+        let mut sim = food_ahead(FoodKind::SlowDown);
+        sim.speed = MIN_SPEED;
+        sim.step(None);
+        assert_eq!(sim.speed(), MIN_SPEED);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::controller::{self, ControllerKind};
+use crate::level::Level;
+use crate::sim::Simulation;
+
+/// A level and the controllers driving its snakes, one per player, to play
+/// many games of.
+#[derive(Debug, Clone)]
+pub struct Matchup {
+    pub level: Level,
+    pub controllers: Vec<ControllerKind>,
+}
+
+/// How one snake's game ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnakeRecord {
+    pub score: u32,
+    pub length: usize,
+    /// What killed it, or `None` if it lived to the end.
+    pub death: Option<&'static str>,
+}
+
+/// How one game ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    pub seed: u64,
+    pub ticks: u32,
+    /// Whether the snakes filled the board.
+    pub won: bool,
+    /// Whether the game was stopped at the tick limit.
+    pub timed_out: bool,
+    /// Who won: the only snake on a filled board, or the winner of a versus
+    /// game. `None` for lost single-player games and draws.
+    pub winner: Option<usize>,
+    pub snakes: Vec<SnakeRecord>,
+}
+
+/// Plays one game of `matchup` to the end, or until `max_ticks` ticks were
+/// played.
+pub fn play(matchup: &Matchup, seed: u64, max_ticks: u32) -> GameRecord {
+    let players = matchup.controllers.len();
+    let mut sim = Simulation::with_players(matchup.level.clone(), seed, players);
+    let mut controllers: Vec<_> = matchup.controllers.iter().map(|kind| kind.build()).collect();
+    while !sim.is_over() && sim.ticks() < max_ticks {
+        controller::steer_all(&mut sim, &mut controllers);
+        sim.tick();
+    }
+
+    let winner = if players > 1 { sim.winner() } else { sim.is_won().then_some(0) };
+    GameRecord {
+        seed,
+        ticks: sim.ticks(),
+        won: sim.is_won(),
+        timed_out: !sim.is_over(),
+        winner,
+        snakes: sim
+            .players()
+            .iter()
+            .map(|p| SnakeRecord { score: p.score(), length: p.snake().len(), death: p.death().map(|ate| ate.name()) })
+            .collect(),
+    }
+}
+
+/// Plays `games` games of every matchup, with seeds counting up from
+/// `first_seed`, spread over `threads` threads. Every matchup gets the same
+/// seeds, so they can be compared fairly.
+///
+/// The records come back per matchup, in seed order.
+pub fn run(matchups: &[Matchup], games: usize, first_seed: u64, max_ticks: u32, threads: usize) -> Vec<Vec<GameRecord>> {
+    let jobs = matchups.len() * games;
+    let next = AtomicUsize::new(0);
+    let mut finished: Vec<(usize, GameRecord)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let job = next.fetch_add(1, Ordering::Relaxed);
+                        if job >= jobs {
+                            return done;
+                        }
+                        let (matchup, game) = (job / games, job % games);
+                        done.push((job, play(&matchups[matchup], first_seed + game as u64, max_ticks)));
+                    }
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().expect("simulation thread panicked")).collect()
+    });
+
+    let mut records: Vec<Vec<GameRecord>> = matchups.iter().map(|_| Vec::with_capacity(games)).collect();
+    finished.sort_by_key(|&(job, _)| job);
+    for (job, record) in finished {
+        records[job / games].push(record);
+    }
+    records
+}
+
+/// Mean and median of a set of numbers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Average {
+    pub mean: f64,
+    pub median: f64,
+}
+
+impl Average {
+    pub fn of(values: impl IntoIterator<Item = f64>) -> Self {
+        let mut values: Vec<f64> = values.into_iter().collect();
+        if values.is_empty() {
+            return Average::default();
+        }
+        values.sort_by(f64::total_cmp);
+        let mid = values.len() / 2;
+        let median = if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] };
+        Average { mean: values.iter().sum::<f64>() / values.len() as f64, median }
+    }
+}
+
+/// How one player of a matchup did over all its games.
+#[derive(Debug, Clone)]
+pub struct PlayerSummary {
+    pub controller: ControllerKind,
+    pub score: Average,
+    pub length: Average,
+    pub wins: usize,
+    /// How often each cause of death came up, most common first. Snakes
+    /// that lived to the end count as `survived`.
+    pub deaths: Vec<(&'static str, usize)>,
+}
+
+/// Statistics over all games of one matchup.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub level: String,
+    pub games: usize,
+    pub ticks: Average,
+    /// Games where the board was filled.
+    pub won: usize,
+    pub timeouts: usize,
+    /// Versus games nobody won.
+    pub draws: usize,
+    pub players: Vec<PlayerSummary>,
+}
+
+impl Summary {
+    pub fn new(matchup: &Matchup, records: &[GameRecord]) -> Self {
+        let players = matchup
+            .controllers
+            .iter()
+            .enumerate()
+            .map(|(i, &controller)| {
+                let mut deaths: Vec<(&'static str, usize)> = Vec::new();
+                for record in records {
+                    let cause = record.snakes[i].death.unwrap_or("survived");
+                    match deaths.iter_mut().find(|(name, _)| *name == cause) {
+                        Some((_, count)) => *count += 1,
+                        None => deaths.push((cause, 1)),
+                    }
+                }
+                deaths.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+                PlayerSummary {
+                    controller,
+                    score: Average::of(records.iter().map(|r| r.snakes[i].score as f64)),
+                    length: Average::of(records.iter().map(|r| r.snakes[i].length as f64)),
+                    wins: records.iter().filter(|r| r.winner == Some(i)).count(),
+                    deaths,
+                }
+            })
+            .collect();
+
+        Summary {
+            level: matchup.level.name.clone(),
+            games: records.len(),
+            ticks: Average::of(records.iter().map(|r| r.ticks as f64)),
+            won: records.iter().filter(|r| r.won).count(),
+            timeouts: records.iter().filter(|r| r.timed_out).count(),
+            draws: if matchup.controllers.len() > 1 { records.iter().filter(|r| r.winner.is_none()).count() } else { 0 },
+            players,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(winner: Option<usize>, deaths: &[Option<&'static str>]) -> GameRecord {
+        GameRecord {
+            seed: 0,
+            ticks: 10,
+            won: false,
+            timed_out: false,
+            winner,
+            snakes: deaths.iter().map(|&death| SnakeRecord { score: 1, length: 3, death }).collect(),
+        }
+    }
+
+    #[test]
+    fn average_takes_the_middle_of_odd_and_even_counts() {
+        let odd = Average::of([5.0, 1.0, 3.0]);
+        assert_eq!((odd.mean, odd.median), (3.0, 3.0));
+        let even = Average::of([4.0, 1.0, 10.0, 2.0]);
+        assert_eq!((even.mean, even.median), (4.25, 3.0));
+        let empty = Average::of([]);
+        assert_eq!((empty.mean, empty.median), (0.0, 0.0));
+    }
+
+    #[test]
+    fn summary_counts_deaths_most_common_first() {
+        let matchup = Matchup { level: Level::basic_levels().remove(0), controllers: vec![ControllerKind::Greedy] };
+        let records = [
+            record(None, &[Some("wall")]),
+            record(None, &[Some("self")]),
+            record(None, &[Some("wall")]),
+            record(Some(0), &[None]),
+            record(None, &[Some("self")]),
+            record(None, &[Some("wall")]),
+        ];
+        let summary = Summary::new(&matchup, &records);
+        assert_eq!(summary.games, 6);
+        assert_eq!(summary.draws, 0);
+        let player = &summary.players[0];
+        assert_eq!(player.wins, 1);
+        assert_eq!(player.deaths, vec![("wall", 3), ("self", 2), ("survived", 1)]);
+    }
+
+    #[test]
+    fn summary_counts_versus_wins_and_draws() {
+        let matchup = Matchup {
+            level: Level::basic_levels().remove(0),
+            controllers: vec![ControllerKind::Greedy, ControllerKind::Bfs],
+        };
+        let records = [
+            record(Some(1), &[Some("wall"), None]),
+            record(None, &[Some("head"), Some("head")]),
+            record(Some(1), &[Some("snake"), None]),
+            record(Some(0), &[None, Some("self")]),
+        ];
+        let summary = Summary::new(&matchup, &records);
+        assert_eq!(summary.draws, 1);
+        assert_eq!(summary.players[0].wins, 1);
+        assert_eq!(summary.players[1].wins, 2);
+        assert_eq!(summary.players[1].deaths, vec![("survived", 2), ("head", 1), ("self", 1)]);
+    }
+
+    #[test]
+    fn run_keeps_seed_order_whatever_the_threads() {
+        let level = Level::basic_levels().remove(0);
+        let matchups = [
+            Matchup { level: level.clone(), controllers: vec![ControllerKind::Greedy] },
+            Matchup { level, controllers: vec![ControllerKind::Greedy, ControllerKind::Bfs] },
+        ];
+        let one = run(&matchups, 5, 7, 300, 1);
+        let four = run(&matchups, 5, 7, 300, 4);
+        assert_eq!(one, four);
+        for (matchup, records) in matchups.iter().zip(&one) {
+            let seeds: Vec<u64> = records.iter().map(|r| r.seed).collect();
+            assert_eq!(seeds, vec![7, 8, 9, 10, 11]);
+            assert_eq!(records[2], play(matchup, 9, 300));
+        }
+    }
+}